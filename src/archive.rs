@@ -0,0 +1,142 @@
+use crate::config::{Config, SinkSpec};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::{Builder, Header};
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//
+// Run artifacts bundled into one .tar.gz for
+// upload: stream files as they are and the result.
+// Archive is written aside and renamed into place,
+// so a consumer never picks up a partial one.
+//
+
+const RESULT_NAME: &str = "result.json";
+
+// Files the child could have written, missing ones are skipped
+pub fn output_files(config: &Config) -> Vec<String> {
+    let streams = &config.streams;
+    let mut files: Vec<String> = Vec::new();
+
+    let sinks = streams.sinks.stdout.iter().chain(streams.sinks.stderr.iter());
+    let sink_files = sinks.filter_map(|sink| match sink {
+        SinkSpec::File { path, .. } => Some(path.as_str()),
+        _ => None,
+    });
+
+    let paths = [streams.stdout.path(), streams.stderr.path(), streams.combined.as_deref()];
+    for path in paths.into_iter().flatten().chain(sink_files) {
+        if !files.iter().any(|val| val == path) {
+            files.push(path.to_string());
+        }
+    }
+
+    files
+}
+
+pub fn create(path: &str, files: &[String], result: &[u8]) -> Result<(), String> {
+    let tmp_path = format!("{path}.tmp");
+
+    if let Err(e) = write_archive(&tmp_path, files, result) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(format!("Failed to write archive. Path: {path}. Reason - {e}"));
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(format!("Failed to move archive into place. Path: {path}. Reason - {e}"));
+    }
+
+    Ok(())
+}
+
+fn write_archive(path: &str, files: &[String], result: &[u8]) -> io::Result<()> {
+    let gz = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+    let mut tar = Builder::new(gz);
+
+    for file in files {
+        let metadata = match fs::metadata(file) {
+            Ok(val) => val,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        let mut header = Header::new_gnu();
+        header.set_size(metadata.len());
+        header.set_mode(metadata.mode() & 0o7777);
+        header.set_mtime(metadata.mtime() as u64);
+
+        // Size is fixed by the header, file may not change meanwhile
+        let mut data = File::open(file)?.take(metadata.len());
+        tar.append_data(&mut header, entry_name(file), &mut data)?;
+        if data.limit() != 0 {
+            let reason = format!("File shrunk while archived. Path: {file}");
+            return Err(io::Error::other(reason));
+        }
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|val| val.as_secs()).unwrap_or(0);
+    let mut header = Header::new_gnu();
+    header.set_size(result.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(now);
+    tar.append_data(&mut header, RESULT_NAME, result)?;
+
+    let mut file = tar.into_inner()?.finish()?;
+    file.flush()?;
+    file.get_ref().sync_all()
+}
+
+// Relative names only, like tar does by default
+fn entry_name(path: &str) -> String {
+    let mut name = path;
+    while let Some(val) = name.strip_prefix('/').or_else(|| name.strip_prefix("./")) {
+        name = val;
+    }
+
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process::Command;
+
+    #[test]
+    fn entry_names_are_relative() {
+        assert_eq!(entry_name("/var/log/out.log"), "var/log/out.log");
+        assert_eq!(entry_name(".//./out.log"), "out.log");
+        assert_eq!(entry_name("logs/out.log"), "logs/out.log");
+    }
+
+    #[test]
+    fn archive_is_read_by_tar() {
+        let dir = env::temp_dir().join("runner-unit-archive");
+        fs::create_dir_all(&dir).unwrap();
+
+        let long = dir.join(format!("{}/{}", "d".repeat(110), "out.log"));
+        fs::create_dir_all(long.parent().unwrap()).unwrap();
+        fs::write(&long, "x".repeat(1000)).unwrap();
+
+        let path = dir.join("run.tar.gz").to_string_lossy().into_owned();
+        let files = [long.to_string_lossy().into_owned()];
+        create(&path, &files, b"{}").unwrap();
+
+        let output = Command::new("tar").args(["-tzf", &path]).output().unwrap();
+        assert!(output.status.success());
+
+        let listing = String::from_utf8(output.stdout).unwrap();
+        let names: Vec<&str> = listing.lines().collect();
+        assert_eq!(names, [entry_name(&files[0]).as_str(), RESULT_NAME]);
+
+        let output = Command::new("tar").args(["-xzOf", &path, RESULT_NAME]).output().unwrap();
+        assert_eq!(output.stdout, b"{}");
+    }
+}
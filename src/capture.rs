@@ -0,0 +1,103 @@
+use crate::timestamp;
+
+use serde::Serialize;
+
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem;
+
+//
+// Child output kept for the result. Small outputs
+// stay in memory, once the threshold is crossed the
+// data is moved to a temporary file so the memory
+// of the runner stays bounded.
+//
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Captured {
+    Text(String),
+    Path(String),
+}
+
+pub struct Capture {
+    name: &'static str,
+    threshold: u64,
+    buffer: Vec<u8>,
+    spill: Option<(String, BufWriter<File>)>,
+    failed: bool,
+}
+
+fn spill_file(name: &str) -> Result<(String, File), String> {
+    let unique = (timestamp::now_precise() * 1e6) as u64;
+    let file_name = format!("sbxbin-{}-{unique}-{name}.log", std::process::id());
+    let path = env::temp_dir().join(file_name).to_string_lossy().into_owned();
+
+    match File::options().write(true).create_new(true).open(&path) {
+        Ok(val) => Ok((path, val)),
+        Err(e) => Err(format!("Failed to create spill file. Path: {path}. Reason - {e}")),
+    }
+}
+
+impl Capture {
+    pub fn new(name: &'static str, threshold: u64) -> Self {
+        Self {
+            name,
+            threshold,
+            buffer: Vec::new(),
+            spill: None,
+            failed: false,
+        }
+    }
+
+    fn start_spill(&mut self) -> Result<(), String> {
+        let (path, file) = spill_file(self.name)?;
+        let mut file = BufWriter::new(file);
+
+        if let Err(e) = file.write_all(&self.buffer) {
+            return Err(format!("Failed to write spill file. Reason - {e}"));
+        }
+
+        eprintln!("Captured {} is spilled to '{path}'", self.name);
+        self.buffer = Vec::new();
+        self.spill = Some((path, file));
+        Ok(())
+    }
+
+    // Never fails, the pipe must be drained either way
+    pub fn write(&mut self, data: &[u8]) {
+        if self.failed {
+            return;
+        }
+
+        let size = (self.buffer.len() + data.len()) as u64;
+        if self.spill.is_none() && size > self.threshold {
+            if let Err(e) = self.start_spill() {
+                eprintln!("{e}. Rest of {} is dropped", self.name);
+                self.failed = true;
+                return;
+            }
+        }
+
+        match &mut self.spill {
+            Some((_, file)) => {
+                if let Err(e) = file.write_all(data) {
+                    eprintln!("Failed to write spill file. Reason - {e}");
+                    self.failed = true;
+                }
+            }
+            None => self.buffer.extend_from_slice(data),
+        }
+    }
+
+    pub fn finish(&mut self) -> Captured {
+        match self.spill.take() {
+            Some((path, mut file)) => {
+                let _ = file.flush();
+                Captured::Path(path)
+            }
+            None => Captured::Text(String::from_utf8_lossy(&mem::take(&mut self.buffer)).into_owned()),
+        }
+    }
+}
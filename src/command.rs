@@ -0,0 +1,200 @@
+use crate::config::{ArgsFromDir, Config};
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+//
+// Command file holds NUL separated arguments,
+// same as /proc/<pid>/cmdline. This allows
+// bytes which can not be encoded in JSON.
+//
+
+fn load_command_file(path: &str) -> Result<Vec<OsString>, String> {
+    let content = match fs::read(path) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to read command file. Reason - {e}")),
+    };
+
+    let content = content.strip_suffix(b"\0").unwrap_or(&content);
+
+    let argv: Vec<OsString> = content
+        .split(|&b| b == 0)
+        .map(|arg| OsString::from_vec(arg.to_vec()))
+        .collect();
+
+    if argv.len() == 1 && argv[0].is_empty() {
+        return Err(format!("Command file is empty. Path: {path}"));
+    }
+
+    Ok(argv)
+}
+
+pub fn resolve(config: &Config) -> Result<Vec<OsString>, String> {
+    let mut argv = match &config.command_file {
+        Some(path) => load_command_file(path)?,
+        None => return Ok(config.command.iter().map(OsString::from).collect()),
+    };
+
+    // Inline command is already resolved on config load
+    if config.command_relative_to_cwd {
+        argv[0] = relative_to_cwd(&argv[0], &config.cwd)?.into_os_string();
+    }
+
+    Ok(argv)
+}
+
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(val) => val.is_file() && val.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+//
+// Resolves program against cwd, even a bare name, and
+// returns its absolute path. The child changes into cwd
+// before exec, so relative paths would break otherwise.
+// Symlinks are kept for multi-call binaries.
+//
+
+pub fn relative_to_cwd(program: &OsStr, cwd: &str) -> Result<PathBuf, String> {
+    let path = match std::path::absolute(Path::new(cwd).join(program)) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to resolve program path. Reason - {e}")),
+    };
+
+    if !path.exists() {
+        return Err(format!(
+            "Program '{}' not found in cwd '{cwd}'",
+            program.to_string_lossy()
+        ));
+    }
+
+    if !is_executable(&path) {
+        return Err(format!("Program '{}' is not an executable file", path.display()));
+    }
+
+    Ok(path)
+}
+
+// Looked up in PATH by exec, unlike a path
+pub fn is_bare_name(program: &OsStr) -> bool {
+    !program.as_bytes().contains(&b'/')
+}
+
+//
+// Locates program the way exec does: paths with a slash
+// are taken relative to cwd, bare names are looked up
+// in PATH the child gets, not the one of the runner.
+//
+
+pub fn find_executable(program: &OsStr, cwd: &str, path: Option<&OsStr>) -> Option<PathBuf> {
+    if !is_bare_name(program) {
+        let path = Path::new(cwd).join(program);
+        return is_executable(&path).then_some(path);
+    }
+
+    let paths = path.unwrap_or(OsStr::new("/usr/bin:/bin"));
+    env::split_paths(paths)
+        .map(|dir| Path::new(cwd).join(dir).join(program))
+        .find(|path| is_executable(path))
+}
+
+//
+// Regular files of the directory, symlinks followed,
+// sorted by name so that the order does not depend
+// on the filesystem. Paths keep dir as given, the
+// child resolves them against cwd like the runner.
+//
+
+fn dir_files(args: &ArgsFromDir, cwd: &str) -> Result<Vec<OsString>, String> {
+    let entries = match fs::read_dir(Path::new(cwd).join(&args.dir)) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to read args directory '{}'. Reason - {e}", args.dir)),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = match entry {
+            Ok(val) => val,
+            Err(e) => return Err(format!("Failed to read args directory '{}'. Reason - {e}", args.dir)),
+        };
+
+        if fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_file()) {
+            names.push(entry.file_name());
+        }
+    }
+
+    // Running with no inputs at all would change what the target does
+    if names.is_empty() {
+        return Err(format!("Args directory '{}' has no files", args.dir));
+    }
+
+    names.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+    Ok(names.into_iter().map(|name| Path::new(&args.dir).join(name).into_os_string()).collect())
+}
+
+fn expand_args(argv: Vec<OsString>, config: &Config) -> Result<Vec<OsString>, String> {
+    let args = match &config.args_from_dir {
+        Some(val) => val,
+        None => return Ok(argv),
+    };
+
+    let placeholder = OsStr::new(&args.placeholder);
+    if !argv.iter().any(|arg| arg == placeholder) {
+        return Err(format!("Placeholder '{}' is missing from command", args.placeholder));
+    }
+
+    let files = dir_files(args, &config.cwd)?;
+    let mut expanded = Vec::with_capacity(argv.len() + files.len());
+
+    for arg in argv {
+        if arg == placeholder {
+            expanded.extend(files.iter().cloned());
+        } else {
+            expanded.push(arg);
+        }
+    }
+
+    Ok(expanded)
+}
+
+//
+// First of command_alternatives whose program exists
+// on this host, otherwise the command itself.
+// Returns index of the chosen alternative.
+//
+// Bare name is replaced with the program found, spawn
+// would search PATH of the runner, not of the child.
+//
+
+pub fn select(config: &Config, path: Option<&OsStr>) -> Result<(Vec<OsString>, Option<usize>), String> {
+    let alternative = config.command_alternatives.iter().enumerate().find_map(|(index, argv)| {
+        find_executable(OsStr::new(&argv[0]), &config.cwd, path).map(|program| (index, program))
+    });
+
+    let (argv, alternative) = match alternative {
+        Some((index, program)) => {
+            let mut argv: Vec<OsString> = config.command_alternatives[index].iter().map(OsString::from).collect();
+            if is_bare_name(&argv[0]) {
+                argv[0] = program.into_os_string();
+            }
+
+            (argv, Some(index))
+        }
+        None => (resolve(config)?, None),
+    };
+
+    Ok((expand_args(argv, config)?, alternative))
+}
+
+pub fn display(argv: &[OsString]) -> String {
+    argv.iter()
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
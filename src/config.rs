@@ -1,50 +1,1492 @@
-use serde::Deserialize;
+use crate::publish;
+use crate::regex::Regex;
+use crate::result::ExitReason;
+use crate::signals::{self, Signal};
+use crate::triage::{self, ReasonPath};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileMode {
+    #[default]
+    Truncate,
+    Append,
+}
+
+#[derive(Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Stream {
+    #[default]
+    Null,
+    Inherit,
+    File {
+        path: String,
+        #[serde(default)]
+        mode: FileMode,
+    },
+    Pipe,
+    Fd {
+        fd: i32,
+    },
+    Capture,
+}
+
+impl Stream {
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Stream::File { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    // Stream is free to be consumed by the runner
+    pub fn is_unused(&self) -> bool {
+        matches!(self, Stream::Null | Stream::Pipe)
+    }
+}
+
+//
+// Besides tagged form streams accept
+// null and plain path, which means
+// /dev/null and truncated file
+//
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StreamSpec {
+    Path(String),
+    Tagged(Stream),
+}
+
+fn deserialize_stream<'de, D>(deserializer: D) -> Result<Stream, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let stream = match Option::<StreamSpec>::deserialize(deserializer)? {
+        None => Stream::Null,
+        Some(StreamSpec::Path(path)) => Stream::File {
+            path,
+            mode: FileMode::Truncate,
+        },
+        Some(StreamSpec::Tagged(stream)) => stream,
+    };
+
+    Ok(stream)
+}
 
 #[derive(Deserialize)]
 pub struct StreamRedirection {
-    pub stdin: Option<String>,
-    pub stdout: Option<String>,
-    pub stderr: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_stream")]
+    pub stdin: Stream,
+    #[serde(default, deserialize_with = "deserialize_stream")]
+    pub stdout: Stream,
+    #[serde(default, deserialize_with = "deserialize_stream")]
+    pub stderr: Stream,
+    #[serde(default)]
+    pub combined: Option<String>,
+    #[serde(default)]
+    pub syslog: Option<SyslogRedirection>,
+    #[serde(default)]
+    pub sinks: Sinks,
+    #[serde(default)]
+    pub flush_policy: FlushPolicy,
+    #[serde(default)]
+    pub rotate_interval_sec: Option<u64>,
+    #[serde(default)]
+    pub reopen_logs_on_sighup: bool,
+    #[serde(default = "default_capture_spill_bytes")]
+    pub capture_spill_bytes: u64,
+    #[serde(default)]
+    pub drain_timeout_sec: Option<u64>,
+    #[serde(default)]
+    pub sequential_stdin: bool,
+    #[serde(default)]
+    pub tag_style: TagStyle,
+}
+
+fn default_capture_spill_bytes() -> u64 {
+    1024 * 1024
+}
+
+//
+// Prefix marking the source stream of each line
+// in combined output and tagged sink files
+//
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagStyle {
+    #[default]
+    Brackets,
+    Short,
+    None,
+}
+
+impl TagStyle {
+    pub fn tags(&self) -> (&'static [u8], &'static [u8]) {
+        match self {
+            TagStyle::Brackets => (b"[out] ", b"[err] "),
+            TagStyle::Short => (b"O: ", b"E: "),
+            TagStyle::None => (b"", b""),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FlushPolicy {
+    #[default]
+    Line,
+    Block,
+    Immediate,
+}
+
+fn default_syslog_facility() -> String {
+    String::from("user")
+}
+
+fn default_syslog_tag() -> String {
+    String::from("runner")
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SyslogRedirection {
+    #[serde(default = "default_syslog_facility")]
+    pub facility: String,
+    #[serde(default = "default_syslog_tag")]
+    pub tag: String,
+    #[serde(default)]
+    pub stdout: bool,
+    #[serde(default)]
+    pub stderr: bool,
+}
+
+//
+// Destinations a proxied stream is copied to,
+// every chunk goes to each of them in order
+//
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkSpec {
+    File {
+        path: String,
+        #[serde(default)]
+        mode: FileMode,
+        // Prefix lines with stream tags, like combined
+        #[serde(default)]
+        tag: bool,
+    },
+    Syslog {
+        #[serde(default = "default_syslog_facility")]
+        facility: String,
+        #[serde(default = "default_syslog_tag")]
+        tag: String,
+    },
+    Inherit,
+}
+
+//
+// Where the result is published once the run
+// is over, as a single JSON line. Webhook is
+// a plain http:// url, TLS is not supported.
+//
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ResultSink {
+    File { path: String },
+    Unix { path: String },
+    Tcp { address: String },
+    Webhook { url: String },
+}
+
+#[derive(Deserialize, Default)]
+pub struct Sinks {
+    #[serde(default)]
+    pub stdout: Vec<SinkSpec>,
+    #[serde(default)]
+    pub stderr: Vec<SinkSpec>,
+}
+
+//
+// Resource limits applied to the child right before exec.
+// Each of them becomes both soft and hard rlimit.
+//
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Limits {
+    #[serde(default)]
+    pub cpu_time_sec: Option<u64>,
+    #[serde(default)]
+    pub memory_bytes: Option<u64>,
+    #[serde(default)]
+    pub max_open_files: Option<u64>,
+    #[serde(default)]
+    pub output_bytes: Option<u64>,
+    // RLIMIT_NPROC counts all processes and threads of the uid
+    #[serde(default)]
+    pub max_threads: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.cpu_time_sec.is_none()
+            && self.memory_bytes.is_none()
+            && self.max_open_files.is_none()
+            && self.output_bytes.is_none()
+            && self.max_threads.is_none()
+    }
+}
+
+//
+// IO scheduling class, as in ioprio_set(2).
+// Realtime class needs CAP_SYS_ADMIN.
+//
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IoniceClass {
+    Realtime = 1,
+    BestEffort = 2,
+    Idle = 3,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvironmentalVariableMode {
+    #[default]
+    Set,
+    Prepend,
+    Append,
+}
+
+//
+// Argument equal to placeholder is replaced
+// by files of dir, which is relative to cwd
+//
+
+#[derive(Deserialize, Clone)]
+pub struct ArgsFromDir {
+    pub placeholder: String,
+    pub dir: String,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+// What run timeout is measured for when the child is restarted
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutScope {
+    #[default]
+    Attempt,
+    Total,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Plain,
+    Json,
+    Csv,
+}
+
+fn default_print_exit_code() -> bool {
+    true
+}
+
+fn default_separator() -> String {
+    String::from(":")
 }
 
 #[derive(Deserialize)]
 pub struct EnvironmentalVariable {
     pub name: String,
     pub value: String,
+    #[serde(default)]
+    pub mode: EnvironmentalVariableMode,
+    #[serde(default = "default_separator")]
+    pub separator: String,
+    // Overrides expand_env for this value
+    #[serde(default)]
+    pub raw: Option<bool>,
+}
+
+//
+// Besides list of variables env accepts
+// an object of names to values, which
+// are set as is. Shape is checked first,
+// so errors point into the form used.
+//
+
+fn deserialize_env<'de, D>(deserializer: D) -> Result<Vec<EnvironmentalVariable>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+
+    let env = match Value::deserialize(deserializer)? {
+        list @ Value::Array(_) => serde_json::from_value(list).map_err(D::Error::custom)?,
+        map @ Value::Object(_) => serde_json::from_value::<BTreeMap<String, String>>(map)
+            .map_err(D::Error::custom)?
+            .into_iter()
+            .map(|(name, value)| EnvironmentalVariable {
+                name,
+                value,
+                mode: EnvironmentalVariableMode::Set,
+                separator: default_separator(),
+                raw: None,
+            })
+            .collect(),
+        _ => return Err(D::Error::custom("env must be a list of variables or an object of names to values")),
+    };
+
+    Ok(env)
+}
+
+fn default_sigterm_attempts() -> u32 {
+    1
+}
+
+fn default_restart_window_sec() -> u64 {
+    60
+}
+
+fn default_ready_timeout_sec() -> u64 {
+    60
+}
+
+fn default_adaptive_poll_max_ms() -> u64 {
+    5000
+}
+
+fn default_handle_signals() -> Vec<Signal> {
+    signals::SHUTDOWN_SIGNALS.into_iter().map(Signal).collect()
+}
+
+fn default_shutdown_poll_ms() -> u64 {
+    50
+}
+
+fn default_pty_rows() -> u16 {
+    24
+}
+
+fn default_pty_cols() -> u16 {
+    80
+}
+
+fn default_pre_kill_timeout_sec() -> u64 {
+    10
+}
+
+fn default_health_check_interval_sec() -> u64 {
+    30
+}
+
+fn default_health_check_timeout_sec() -> u64 {
+    10
+}
+
+fn default_health_check_failures() -> u32 {
+    3
+}
+
+fn default_verify_timeout_sec() -> u64 {
+    60
+}
+
+fn default_result_sink_timeout_sec() -> u64 {
+    10
 }
 
 #[derive(Deserialize)]
 pub struct Config {
     pub cwd: String,
+    #[serde(default)]
     pub command: Vec<String>,
+    #[serde(default)]
+    pub command_file: Option<String>,
+    #[serde(default)]
+    pub command_relative_to_cwd: bool,
+    #[serde(default)]
+    pub command_alternatives: Vec<Vec<String>>,
+    #[serde(default)]
+    pub args_from_dir: Option<ArgsFromDir>,
+    #[serde(default)]
+    pub argv0: Option<String>,
+    #[serde(default)]
+    pub hash_binary: bool,
+    #[serde(deserialize_with = "deserialize_env")]
     pub env: Vec<EnvironmentalVariable>,
+    #[serde(default)]
+    pub expand_env: bool,
     pub streams: StreamRedirection,
+    #[serde(default)]
+    pub output_must_be_under: Option<String>,
     pub poll_interval_ms: u64,
-    pub run_timeout_sec: u64,
+    #[serde(default)]
+    pub run_timeout_sec: Option<u64>,
+    #[serde(default)]
+    pub run_timeout_ms: Option<u64>,
     pub grace_period_sec: u64,
+    #[serde(default = "default_sigterm_attempts")]
+    pub sigterm_attempts: u32,
+    #[serde(default)]
+    pub sigterm_interval_sec: Option<u64>,
+    #[serde(default)]
+    pub verbose: bool,
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+    #[serde(default = "default_restart_window_sec")]
+    pub restart_window_sec: u64,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    #[serde(default)]
+    pub restart_timeout_scope: TimeoutScope,
+    // Empty means any failure is restarted
+    #[serde(default)]
+    pub retry_on_exit_codes: Vec<u32>,
+    #[serde(default)]
+    pub inject_run_id: Option<String>,
+    #[serde(default)]
+    pub run_id: Option<String>,
+    #[serde(default)]
+    pub inject_timeout_env: bool,
+    #[serde(default)]
+    pub output_format: OutputFormat,
+    #[serde(default = "default_print_exit_code")]
+    pub print_exit_code: bool,
+    #[serde(default)]
+    pub cpu_affinity: Option<Vec<usize>>,
+    #[serde(default)]
+    pub timeout_exit_code: Option<i32>,
+    #[serde(default)]
+    pub terminated_exit_code: Option<i32>,
+    #[serde(default)]
+    pub exit_with_child_code: bool,
+    #[serde(default)]
+    pub success_exit_codes: Vec<u32>,
+    #[serde(default)]
+    pub exit_codes: HashMap<ExitReason, i32>,
+    #[serde(default)]
+    pub shutdown_signal: Signal,
+    #[serde(default = "default_handle_signals")]
+    pub handle_signals: Vec<Signal>,
+    #[serde(default = "default_shutdown_poll_ms")]
+    pub shutdown_poll_ms: u64,
+    #[serde(default)]
+    pub shutdown_settle_ms: u64,
+    #[serde(default)]
+    pub start_delay_sec: Option<u64>,
+    // Shared by start delay, all attempts and verify command
+    #[serde(default)]
+    pub total_timeout_sec: Option<u64>,
+    #[serde(default)]
+    pub ready_file: Option<String>,
+    #[serde(default = "default_ready_timeout_sec")]
+    pub ready_timeout_sec: u64,
+    #[serde(default)]
+    pub adaptive_poll: bool,
+    #[serde(default = "default_adaptive_poll_max_ms")]
+    pub adaptive_poll_max_ms: u64,
+    #[serde(default)]
+    pub require_nonempty_env: bool,
+    #[serde(default)]
+    pub inherit_env_allowlist: Option<Vec<String>>,
+    #[serde(default)]
+    pub limits: Limits,
+    // Moved into limits, kept only to reject old configs
+    #[serde(default, rename = "max_open_files")]
+    pub legacy_max_open_files: Option<serde_json::Value>,
+    #[serde(default)]
+    pub nice: Option<i32>,
+    #[serde(default)]
+    pub ionice_class: Option<IoniceClass>,
+    #[serde(default)]
+    pub ionice_level: Option<u8>,
+    #[serde(default)]
+    pub exec: bool,
+    #[serde(default)]
+    pub stop_file: Option<String>,
+    #[serde(default)]
+    pub sample_proc_status: bool,
+    #[serde(default)]
+    pub sample_smaps_rollup: bool,
+    #[serde(default)]
+    pub new_session: bool,
+    #[serde(default)]
+    pub netns: Option<String>,
+    #[serde(default)]
+    pub private_tmp: bool,
+    // Kernel default is half of RAM
+    #[serde(default)]
+    pub private_tmp_size_mb: Option<u64>,
+    #[serde(default)]
+    pub kill_on_runner_death: bool,
+    #[serde(default)]
+    pub start_stopped: bool,
+    #[serde(default)]
+    pub start_timeout_sec: Option<u64>,
+    #[serde(default)]
+    pub require_parent_pid: Option<u32>,
+    #[serde(default)]
+    pub drop_capabilities: bool,
+    #[serde(default)]
+    pub use_pty: bool,
+    #[serde(default = "default_pty_rows")]
+    pub pty_rows: u16,
+    #[serde(default = "default_pty_cols")]
+    pub pty_cols: u16,
+    #[serde(default)]
+    pub pre_kill_command: Option<Vec<String>>,
+    #[serde(default = "default_pre_kill_timeout_sec")]
+    pub pre_kill_timeout_sec: u64,
+    #[serde(default)]
+    pub fail_on_stderr_pattern: Option<String>,
+    #[serde(default)]
+    pub result_tail_lines: Option<usize>,
+    #[serde(default)]
+    pub runner_log_file: Option<String>,
+    #[serde(default)]
+    pub health_check_command: Option<Vec<String>>,
+    #[serde(default = "default_health_check_interval_sec")]
+    pub health_check_interval_sec: u64,
+    #[serde(default = "default_health_check_timeout_sec")]
+    pub health_check_timeout_sec: u64,
+    #[serde(default = "default_health_check_failures")]
+    pub health_check_failures: u32,
+    #[serde(default)]
+    pub verify_command: Option<Vec<String>>,
+    #[serde(default = "default_verify_timeout_sec")]
+    pub verify_timeout_sec: u64,
+    // Not used by the runner, copied into result as is
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub archive_output: Option<String>,
+    #[serde(default)]
+    pub result_sink: Option<ResultSink>,
+    #[serde(default = "default_result_sink_timeout_sec")]
+    pub result_sink_timeout_sec: u64,
+    // Filled on load from {reason} placeholders in output paths
+    #[serde(skip)]
+    pub reason_paths: Vec<ReasonPath>,
 }
 
-pub fn load_json(path: &str) -> Result<Config, String> {
+fn read_config(path: &str) -> Result<String, String> {
     let content = match fs::read_to_string(path) {
         Ok(val) => val,
         Err(e) => {
             return Err(format!(
                 "Failed to read config file. Reason - {}",
-                e.to_string()
+                e
             ))
         }
     };
 
-    let config: Config = match serde_json::from_str(&content) {
+    // Comments are allowed only where the extension says so
+    let extension = Path::new(path).extension().and_then(|ext| ext.to_str());
+    match extension {
+        Some("json5") | Some("jsonc") => json5_to_json(&content),
+        _ => Ok(content),
+    }
+}
+
+// Rest of loading works on strict JSON
+fn json5_to_json(content: &str) -> Result<String, String> {
+    let value: Value = match json5::from_str(content) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to parse JSON5 config file. Reason - {e}")),
+    };
+
+    Ok(value.to_string())
+}
+
+pub fn load_json(path: &str) -> Result<Config, String> {
+    parse_json(&read_config(path)?)
+}
+
+//
+// Host-wide defaults every run inherits. Path is taken
+// from the variable, empty value disables the global
+// config. Otherwise the fixed path is used if present.
+//
+
+const GLOBAL_CONFIG_VAR: &str = "SBXBIN_GLOBAL_CONFIG";
+const GLOBAL_CONFIG_PATH: &str = "/etc/sbxbin-runner.json";
+
+fn load_global() -> Result<Option<Value>, String> {
+    let path = match env::var(GLOBAL_CONFIG_VAR) {
+        Ok(val) if val.is_empty() => return Ok(None),
+        Ok(val) => val,
+        Err(_) if Path::new(GLOBAL_CONFIG_PATH).exists() => String::from(GLOBAL_CONFIG_PATH),
+        Err(_) => return Ok(None),
+    };
+
+    let content = match read_config(&path) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to load global config '{path}'. {e}")),
+    };
+
+    match serde_json::from_str::<Value>(&content) {
+        Ok(val) if val.is_object() => Ok(Some(val)),
+        Ok(_) => Err(format!("Global config '{path}' must be an object")),
+        Err(e) => Err(format!("Failed to parse global config '{path}'. Reason - {e}")),
+    }
+}
+
+// Object form of env becomes a list, so that both forms can be joined
+fn env_list(env: Value) -> Vec<Value> {
+    match env {
+        Value::Array(list) => list,
+        Value::Null => Vec::new(),
+        Value::Object(map) => map
+            .into_iter()
+            .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+            .collect(),
+        other => vec![other],
+    }
+}
+
+//
+// Per-run values win. Objects are merged key by key,
+// env lists are joined with per-run variables last,
+// so they are applied on top of global ones. Tagged
+// objects, e.g. streams, are replaced as a whole, as
+// fields of one type mean nothing to another.
+//
+
+fn is_tagged(value: &Value) -> bool {
+    value.get("type").is_some()
+}
+
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(old) if key == "env" && !value.is_null() => {
+                        let mut env = env_list(old.take());
+                        env.extend(env_list(value));
+                        *old = Value::Array(env);
+                    }
+                    Some(old) if old.is_object() && value.is_object() && !is_tagged(&value) => merge(old, value),
+                    _ => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// Setting one of these per run overrides the other one
+const EXCLUSIVE_FIELDS: [(&str, &str); 2] = [
+    ("command", "command_file"),
+    ("run_timeout_sec", "run_timeout_ms"),
+];
+
+fn merge_global(mut global: Value, overlay: Value) -> Value {
+    if let (Value::Object(base), Value::Object(overlay)) = (&mut global, &overlay) {
+        for (a, b) in EXCLUSIVE_FIELDS {
+            if overlay.contains_key(a) {
+                base.remove(b);
+            }
+
+            if overlay.contains_key(b) {
+                base.remove(a);
+            }
+        }
+    }
+
+    merge(&mut global, overlay);
+    global
+}
+
+//
+// Operator's cap on run timeout, unlike the global
+// config it can not be overridden per run. Longer
+// timeouts are rejected, or clamped if asked to.
+//
+
+const MAX_RUN_TIMEOUT_VAR: &str = "SBXBIN_MAX_RUN_TIMEOUT_SEC";
+const MAX_RUN_TIMEOUT_CLAMP_VAR: &str = "SBXBIN_MAX_RUN_TIMEOUT_CLAMP";
+
+fn max_run_timeout() -> Result<Option<(u64, bool)>, String> {
+    let max = match env::var(MAX_RUN_TIMEOUT_VAR) {
+        Ok(val) if val.is_empty() => return Ok(None),
+        Ok(val) => val,
+        Err(_) => return Ok(None),
+    };
+
+    let max = match max.parse::<u64>() {
+        Ok(val) if val > 0 => val,
+        _ => return Err(format!("{MAX_RUN_TIMEOUT_VAR} must be a positive number of seconds, got '{max}'")),
+    };
+
+    let clamp = env::var(MAX_RUN_TIMEOUT_CLAMP_VAR).is_ok_and(|val| val == "1" || val == "true");
+    Ok(Some((max, clamp)))
+}
+
+// Disabled timeout is the longest one
+fn cap_run_timeout(config: &mut Config, max_sec: u64, clamp: bool) -> Result<(), String> {
+    let max = Duration::from_secs(max_sec);
+    if effective_timeout(config).is_none_or(|val| val > max) {
+        if !clamp {
+            return Err(format!("Run timeout exceeds maximum allowed {max_sec} sec"));
+        }
+
+        eprintln!("Run timeout exceeds maximum allowed {max_sec} sec, clamped");
+        match config.run_timeout_ms {
+            Some(_) => config.run_timeout_ms = Some(max_sec.saturating_mul(1000)),
+            None => config.run_timeout_sec = Some(max_sec),
+        }
+    }
+
+    // Restarted attempts each get the run timeout anew
+    let restarted =
+        config.restart_policy != RestartPolicy::Never && config.restart_timeout_scope == TimeoutScope::Attempt;
+    if !restarted || config.total_timeout_sec.is_some_and(|val| val <= max_sec) {
+        return Ok(());
+    }
+
+    if !clamp {
+        return Err(format!("Total timeout of restarted run exceeds maximum allowed {max_sec} sec"));
+    }
+
+    eprintln!("Total timeout of restarted run exceeds maximum allowed {max_sec} sec, clamped");
+    config.total_timeout_sec = Some(max_sec);
+    Ok(())
+}
+
+pub fn parse_json(content: &str) -> Result<Config, String> {
+    let parsed = match load_global()? {
+        None => serde_json::from_str(content),
+        Some(global) => serde_json::from_str(content)
+            .and_then(|overlay| serde_json::from_value(merge_global(global, overlay))),
+    };
+
+    let mut config: Config = match parsed {
         Ok(val) => val,
         Err(e) => {
             return Err(format!(
                 "Failed to parse config file. Reason - {}",
-                e.to_string()
+                e
             ))
         }
     };
 
+    validate(&config)?;
+    triage::resolve(&mut config);
+
+    if let Some((max, clamp)) = max_run_timeout()? {
+        cap_run_timeout(&mut config, max, clamp)?;
+    }
+
+    if config.command_relative_to_cwd && !config.command.is_empty() {
+        let path = crate::command::relative_to_cwd(config.command[0].as_ref(), &config.cwd)?;
+        config.command[0] = path.to_string_lossy().into_owned();
+    }
+
     Ok(config)
 }
+
+pub const NETNS_DIR: &str = "/var/run/netns";
+pub const PRIVATE_TMP_DIR: &str = "/tmp";
+
+//
+// Run timeout as given by either field, validation
+// allows only one. Zero disables the timeout, the
+// child then runs until it exits.
+//
+
+pub fn effective_timeout(config: &Config) -> Option<Duration> {
+    let timeout = match (config.run_timeout_ms, config.run_timeout_sec) {
+        (Some(ms), None) => Duration::from_millis(ms),
+        (None, Some(sec)) => Duration::from_secs(sec),
+        _ => return None,
+    };
+
+    Some(timeout).filter(|val| !val.is_zero())
+}
+
+// What is left of the timeout, a passed deadline leaves zero rather than none
+pub fn remaining_timeout(timeout: Option<Duration>, elapsed: Duration) -> Option<Duration> {
+    timeout.map(|val| val.saturating_sub(elapsed))
+}
+
+fn validate_cpu_affinity(cores: &[usize]) -> Result<(), String> {
+    let cpu_count = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    let cpu_count = (cpu_count.max(1) as usize).min(libc::CPU_SETSIZE as usize);
+
+    if cores.is_empty() {
+        return Err(String::from("cpu_affinity must not be empty"));
+    }
+
+    if let Some(core) = cores.iter().find(|&&core| core >= cpu_count) {
+        return Err(format!(
+            "cpu_affinity core {core} is out of range. CPU count: {cpu_count}"
+        ));
+    }
+
+    Ok(())
+}
+
+//
+// Output files may not exist yet, so the closest existing
+// ancestor is canonicalized and the rest is appended as is.
+// Parent references in the rest can not be resolved that
+// way and are rejected.
+//
+
+fn resolve_output_path(path: &str) -> Result<PathBuf, String> {
+    let absolute = match env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(e) => return Err(format!("Failed to get current directory. Reason - {e}")),
+    };
+
+    let mut existing = absolute.as_path();
+    let mut rest = Vec::new();
+
+    loop {
+        if let Ok(resolved) = fs::canonicalize(existing) {
+            return Ok(rest.iter().rev().fold(resolved, |acc, name| acc.join(name)));
+        }
+
+        match (existing.components().next_back(), existing.parent()) {
+            (Some(Component::Normal(name)), Some(parent)) => {
+                rest.push(name);
+                existing = parent;
+            }
+            _ => return Err(format!("output path '{path}' can not be resolved")),
+        }
+    }
+}
+
+fn validate_output_root(config: &Config, root: &str) -> Result<(), String> {
+    let resolved_root = match fs::canonicalize(root) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("output_must_be_under '{root}' can not be resolved. Reason - {e}")),
+    };
+
+    for path in crate::archive::output_files(config) {
+        if !resolve_output_path(&path)?.starts_with(&resolved_root) {
+            return Err(format!("output path '{path}' is not under '{root}'"));
+        }
+    }
+
+    Ok(())
+}
+
+//
+// Nothing is left to watch the child in exec
+// mode, reject features which need the runner
+//
+
+fn validate_exec(config: &Config) -> Result<(), String> {
+    let streams = &config.streams;
+    let pipes = [&streams.stdin, &streams.stdout, &streams.stderr]
+        .iter()
+        .any(|stream| matches!(stream, Stream::Pipe | Stream::Capture));
+
+    let proxied = streams.combined.is_some()
+        || streams.syslog.is_some()
+        || !streams.sinks.stdout.is_empty()
+        || !streams.sinks.stderr.is_empty()
+        || streams.rotate_interval_sec.is_some()
+        || streams.reopen_logs_on_sighup
+        || config.use_pty;
+    if pipes || proxied {
+        return Err(String::from("exec mode does not support proxied streams"));
+    }
+
+    if config.ready_file.is_some() || config.stop_file.is_some() {
+        return Err(String::from("exec mode does not support ready_file and stop_file"));
+    }
+
+    if config.health_check_command.is_some() {
+        return Err(String::from("exec mode does not support health_check_command"));
+    }
+
+    if config.fail_on_stderr_pattern.is_some() {
+        return Err(String::from("exec mode does not support fail_on_stderr_pattern"));
+    }
+
+    if config.result_tail_lines.is_some() {
+        return Err(String::from("exec mode does not support result_tail_lines"));
+    }
+
+    if config.total_timeout_sec.is_some() {
+        return Err(String::from("exec mode does not support total_timeout_sec"));
+    }
+
+    // There is no runner left to outlive
+    if config.kill_on_runner_death {
+        return Err(String::from("exec mode does not support kill_on_runner_death"));
+    }
+
+    if config.require_parent_pid.is_some() {
+        return Err(String::from("exec mode does not support require_parent_pid"));
+    }
+
+    if config.archive_output.is_some() {
+        return Err(String::from("exec mode does not support archive_output"));
+    }
+
+    if config.start_timeout_sec.is_some() {
+        return Err(String::from("exec mode does not support start_timeout_sec"));
+    }
+
+    if config.result_sink.is_some() {
+        return Err(String::from("exec mode does not support result_sink"));
+    }
+
+    if config.verify_command.is_some() {
+        return Err(String::from("exec mode does not support verify_command"));
+    }
+
+    if crate::archive::output_files(config).iter().any(|path| path.contains(triage::PLACEHOLDER)) {
+        return Err(format!("exec mode does not support {} in output paths", triage::PLACEHOLDER));
+    }
+
+    if config.restart_policy != RestartPolicy::Never {
+        return Err(String::from("exec mode does not support restart_policy"));
+    }
+
+    Ok(())
+}
+
+fn validate(config: &Config) -> Result<(), String> {
+    if config.legacy_max_open_files.is_some() {
+        return Err(String::from("max_open_files has moved, set limits.max_open_files instead"));
+    }
+
+    match (config.command.is_empty(), &config.command_file) {
+        (true, None) => return Err(String::from("Either command or command_file must be set")),
+        (false, Some(_)) => return Err(String::from("command and command_file are mutually exclusive")),
+        _ => {}
+    }
+
+    match (config.run_timeout_sec, config.run_timeout_ms) {
+        (None, None) => return Err(String::from("Either run_timeout_sec or run_timeout_ms must be set")),
+        (Some(_), Some(_)) => return Err(String::from("run_timeout_sec and run_timeout_ms are mutually exclusive")),
+        _ => {}
+    }
+
+    if !config.print_exit_code && config.output_format != OutputFormat::Plain {
+        return Err(String::from("print_exit_code can be disabled only with plain output_format"));
+    }
+
+    // Death signal is tied to the spawning thread, which exits right away
+    if config.start_timeout_sec.is_some() && config.kill_on_runner_death {
+        return Err(String::from("start_timeout_sec can not be used together with kill_on_runner_death"));
+    }
+
+    // Command file is read only at spawn, it is checked there
+    if let Some(args) = &config.args_from_dir {
+        if args.placeholder.is_empty() {
+            return Err(String::from("args_from_dir.placeholder must not be empty"));
+        }
+
+        let commands = std::iter::once(&config.command).chain(&config.command_alternatives);
+        let missing = commands
+            .filter(|argv| !argv.is_empty())
+            .any(|argv| !argv.contains(&args.placeholder));
+
+        if missing {
+            return Err(format!("args_from_dir.placeholder '{}' is missing from command", args.placeholder));
+        }
+    }
+
+    if config.run_id.as_ref().is_some_and(|id| id.is_empty()) {
+        return Err(String::from("run_id must not be empty"));
+    }
+
+    if config.command_alternatives.iter().any(|argv| argv.is_empty()) {
+        return Err(String::from("command_alternatives must not contain empty commands"));
+    }
+
+    let streams = &config.streams;
+    if matches!(streams.stdin, Stream::Capture) {
+        return Err(String::from("stdin can not be captured"));
+    }
+
+    if streams.stdin.path().is_some_and(|path| path.contains(triage::PLACEHOLDER)) {
+        return Err(format!("stdin path can not contain {}", triage::PLACEHOLDER));
+    }
+
+    // Rotated buckets have paths of their own
+    let sorted = crate::archive::output_files(config).iter().any(|path| path.contains(triage::PLACEHOLDER));
+    if sorted && streams.rotate_interval_sec.is_some() {
+        return Err(format!("{} in output paths can not be used with rotate_interval_sec", triage::PLACEHOLDER));
+    }
+
+    if streams.sequential_stdin && streams.stdin.path().is_none() {
+        return Err(String::from("streams.sequential_stdin requires stdin to be a file"));
+    }
+
+    if streams.combined.is_some() && !(streams.stdout.is_unused() && streams.stderr.is_unused()) {
+        return Err(String::from(
+            "streams.combined can not be used together with streams.stdout or streams.stderr"
+        ));
+    }
+
+    if let Some(syslog) = &streams.syslog {
+        if syslog.stdout && (!streams.stdout.is_unused() || streams.combined.is_some()) {
+            return Err(String::from("stdout can not be sent to syslog and file at once"));
+        }
+
+        if syslog.stderr && (!streams.stderr.is_unused() || streams.combined.is_some()) {
+            return Err(String::from("stderr can not be sent to syslog and file at once"));
+        }
+    }
+
+    //
+    // Sinks replace every other destination of the stream
+    //
+
+    let syslog_stdout = streams.syslog.as_ref().is_some_and(|s| s.stdout);
+    let syslog_stderr = streams.syslog.as_ref().is_some_and(|s| s.stderr);
+
+    if !streams.sinks.stdout.is_empty() && (!streams.stdout.is_unused() || streams.combined.is_some() || syslog_stdout) {
+        return Err(String::from("streams.sinks.stdout can not be used with other stdout destinations"));
+    }
+
+    if !streams.sinks.stderr.is_empty() && (!streams.stderr.is_unused() || streams.combined.is_some() || syslog_stderr) {
+        return Err(String::from("streams.sinks.stderr can not be used with other stderr destinations"));
+    }
+
+    if let Some(interval) = streams.rotate_interval_sec {
+        if interval == 0 {
+            return Err(String::from("streams.rotate_interval_sec must be positive"));
+        }
+
+        let files = [&streams.stdout, &streams.stderr]
+            .iter()
+            .any(|stream| matches!(stream, Stream::File { .. }))
+            || streams.sinks.stdout.iter().chain(&streams.sinks.stderr)
+                .any(|sink| matches!(sink, SinkSpec::File { .. }));
+
+        if !files && streams.combined.is_none() {
+            return Err(String::from("streams.rotate_interval_sec requires an output file"));
+        }
+    }
+
+    if streams.reopen_logs_on_sighup {
+        if streams.rotate_interval_sec.is_some() {
+            return Err(String::from("streams.reopen_logs_on_sighup can not be used with rotate_interval_sec"));
+        }
+
+        if config.handle_signals.iter().any(|signal| signal.0 == libc::SIGHUP) {
+            return Err(String::from("SIGHUP can not be in handle_signals with streams.reopen_logs_on_sighup"));
+        }
+    }
+
+    // Terminal has a single output, it goes where stdout is configured
+    let stderr_used = !matches!(streams.stderr, Stream::Null) || syslog_stderr || !streams.sinks.stderr.is_empty();
+    if config.use_pty && stderr_used {
+        return Err(String::from("stderr can not be redirected with use_pty, it goes to stdout"));
+    }
+
+    if config.use_pty && (config.pty_rows == 0 || config.pty_cols == 0) {
+        return Err(String::from("pty_rows and pty_cols must be positive"));
+    }
+
+    // Child exit is watched through SIGCHLD
+    let unhandled = [signal_hook::consts::FORBIDDEN, &[libc::SIGCHLD]].concat();
+    if let Some(signal) = config.handle_signals.iter().find(|signal| unhandled.contains(&signal.0)) {
+        return Err(format!("handle_signals can not contain {}", signals::name(signal.0)));
+    }
+
+    if config.shutdown_poll_ms == 0 {
+        return Err(String::from("shutdown_poll_ms must be positive"));
+    }
+
+    if let Some(argv) = &config.health_check_command {
+        if argv.is_empty() {
+            return Err(String::from("health_check_command must not be empty"));
+        }
+
+        if config.health_check_interval_sec == 0 || config.health_check_timeout_sec == 0 {
+            return Err(String::from("health_check_interval_sec and health_check_timeout_sec must be positive"));
+        }
+
+        if config.health_check_failures == 0 {
+            return Err(String::from("health_check_failures must be positive"));
+        }
+    }
+
+    if let Some(root) = &config.output_must_be_under {
+        validate_output_root(config, root)?;
+    }
+
+    if !config.retry_on_exit_codes.is_empty() && config.restart_policy != RestartPolicy::OnFailure {
+        return Err(String::from("retry_on_exit_codes needs on-failure restart_policy"));
+    }
+
+    if let Some(argv) = &config.verify_command {
+        if argv.is_empty() {
+            return Err(String::from("verify_command must not be empty"));
+        }
+
+        if config.verify_timeout_sec == 0 {
+            return Err(String::from("verify_timeout_sec must be positive"));
+        }
+
+        // Verifier decides the outcome, not the child
+        if config.exit_with_child_code {
+            return Err(String::from("verify_command can not be used together with exit_with_child_code"));
+        }
+    }
+
+    if let Some(pattern) = &config.fail_on_stderr_pattern {
+        if config.use_pty {
+            return Err(String::from("fail_on_stderr_pattern can not be used with use_pty"));
+        }
+
+        Regex::new(pattern)?;
+    }
+
+    if let Some(ResultSink::Webhook { url }) = &config.result_sink {
+        if publish::parse_url(url).is_none() {
+            return Err(format!("result_sink url '{url}' is not http://host[:port][/path]"));
+        }
+    }
+
+    if config.result_sink.is_some() && config.result_sink_timeout_sec == 0 {
+        return Err(String::from("result_sink_timeout_sec must be positive"));
+    }
+
+    if let Some(cores) = &config.cpu_affinity {
+        validate_cpu_affinity(cores)?;
+    }
+
+    // Name of a file in NETNS_DIR, as created by ip netns add
+    if let Some(name) = &config.netns {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(format!("netns '{name}' is not a valid namespace name"));
+        }
+    }
+
+    if config.total_timeout_sec == Some(0) {
+        return Err(String::from("total_timeout_sec must be positive"));
+    }
+
+    if config.result_tail_lines == Some(0) {
+        return Err(String::from("result_tail_lines must be positive"));
+    }
+
+    // Zero would leave the child without its main thread
+    if config.limits.max_threads == Some(0) {
+        return Err(String::from("limits.max_threads must be positive"));
+    }
+
+    // Fresh /tmp would hide the directory behind cwd paths
+    if config.private_tmp && Path::new(&config.cwd).starts_with(PRIVATE_TMP_DIR) {
+        return Err(format!("cwd '{}' can not be inside {PRIVATE_TMP_DIR} with private_tmp", config.cwd));
+    }
+
+    // Same goes for the binary and files the child writes
+    if config.private_tmp {
+        let command = config.command.first().into_iter();
+        let outputs = crate::archive::output_files(config);
+        if let Some(path) = command.chain(outputs.iter()).find(|path| Path::new(path).starts_with(PRIVATE_TMP_DIR)) {
+            return Err(format!("'{path}' can not be inside {PRIVATE_TMP_DIR} with private_tmp"));
+        }
+    }
+
+    if config.private_tmp_size_mb.is_some() && !config.private_tmp {
+        return Err(String::from("private_tmp_size_mb needs private_tmp"));
+    }
+
+    if config.private_tmp_size_mb == Some(0) {
+        return Err(String::from("private_tmp_size_mb must be positive"));
+    }
+
+    if config.nice.is_some_and(|nice| !(-20..=19).contains(&nice)) {
+        return Err(String::from("nice must be in range -20..19"));
+    }
+
+    if config.ionice_level.is_some_and(|level| level > 7) {
+        return Err(String::from("ionice_level must be in range 0..7"));
+    }
+
+    // Idle class has no levels
+    if config.ionice_class == Some(IoniceClass::Idle) && config.ionice_level.is_some() {
+        return Err(String::from("ionice_level can not be used with idle ionice_class"));
+    }
+
+    if config.exec {
+        validate_exec(config)?;
+    }
+
+    Ok(())
+}
+
+//
+// Checks which depend on the host rather than on config
+// itself. Used by validate subcommand, nothing is spawned.
+//
+
+pub fn check_host(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if !Path::new(&config.cwd).is_dir() {
+        problems.push(format!("cwd is not a directory: '{}'", config.cwd));
+    }
+
+    if let Some(name) = &config.netns {
+        if !Path::new(NETNS_DIR).join(name).exists() {
+            problems.push(format!("netns '{name}' does not exist in {NETNS_DIR}"));
+        }
+    }
+
+    if config.private_tmp && !Path::new(PRIVATE_TMP_DIR).is_dir() {
+        problems.push(format!("{PRIVATE_TMP_DIR} is not a directory, private_tmp can not be mounted"));
+    }
+
+    if let Some(path) = &config.command_file {
+        if let Err(e) = crate::command::resolve(config) {
+            problems.push(format!("command_file '{path}' is unusable. {e}"));
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_timeouts(timeouts: Value) -> Config {
+        let mut json = serde_json::json!({
+            "cwd": "/tmp",
+            "command": ["true"],
+            "env": [],
+            "streams": {},
+            "poll_interval_ms": 100,
+            "grace_period_sec": 1,
+        });
+
+        merge(&mut json, timeouts);
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn timeout_in_seconds() {
+        let config = with_timeouts(serde_json::json!({ "run_timeout_sec": 5 }));
+        assert_eq!(effective_timeout(&config), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn timeout_in_milliseconds() {
+        let config = with_timeouts(serde_json::json!({ "run_timeout_ms": 1500 }));
+        assert_eq!(effective_timeout(&config), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn zero_timeout_is_disabled() {
+        let config = with_timeouts(serde_json::json!({ "run_timeout_sec": 0 }));
+        assert_eq!(effective_timeout(&config), None);
+
+        let config = with_timeouts(serde_json::json!({ "run_timeout_ms": 0 }));
+        assert_eq!(effective_timeout(&config), None);
+    }
+
+    #[test]
+    fn passed_deadline_leaves_zero() {
+        let config = with_timeouts(serde_json::json!({ "run_timeout_sec": 5 }));
+        let timeout = effective_timeout(&config);
+
+        assert_eq!(remaining_timeout(timeout, Duration::from_secs(2)), Some(Duration::from_secs(3)));
+        assert_eq!(remaining_timeout(timeout, Duration::from_secs(5)), Some(Duration::ZERO));
+        assert_eq!(remaining_timeout(timeout, Duration::from_secs(7)), Some(Duration::ZERO));
+        assert_eq!(remaining_timeout(None, Duration::from_secs(7)), None);
+    }
+
+    #[test]
+    fn missing_timeout_is_disabled() {
+        let config = with_timeouts(serde_json::json!({}));
+        assert_eq!(effective_timeout(&config), None);
+    }
+
+    #[test]
+    fn timeout_over_cap_is_rejected() {
+        let mut config = with_timeouts(serde_json::json!({ "run_timeout_sec": 7200 }));
+        assert!(cap_run_timeout(&mut config, 3600, false).is_err());
+
+        let mut config = with_timeouts(serde_json::json!({ "run_timeout_sec": 0 }));
+        assert!(cap_run_timeout(&mut config, 3600, false).is_err());
+
+        let mut config = with_timeouts(serde_json::json!({ "run_timeout_ms": 3_600_000 }));
+        assert!(cap_run_timeout(&mut config, 3600, false).is_ok());
+    }
+
+    #[test]
+    fn timeout_over_cap_is_clamped() {
+        let mut config = with_timeouts(serde_json::json!({ "run_timeout_ms": 7_200_000 }));
+        cap_run_timeout(&mut config, 3600, true).unwrap();
+        assert_eq!(effective_timeout(&config), Some(Duration::from_secs(3600)));
+
+        let mut config = with_timeouts(serde_json::json!({ "run_timeout_sec": 0 }));
+        cap_run_timeout(&mut config, 3600, true).unwrap();
+        assert_eq!(effective_timeout(&config), Some(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn restarted_run_is_capped_in_total() {
+        let restarted = serde_json::json!({ "run_timeout_sec": 60, "restart_policy": "always" });
+        let mut config = with_timeouts(restarted.clone());
+        assert!(cap_run_timeout(&mut config, 3600, false).is_err());
+
+        let mut config = with_timeouts(restarted);
+        cap_run_timeout(&mut config, 3600, true).unwrap();
+        assert_eq!(config.total_timeout_sec, Some(3600));
+        assert_eq!(effective_timeout(&config), Some(Duration::from_secs(60)));
+
+        let json = serde_json::json!({ "run_timeout_sec": 60, "restart_policy": "always", "total_timeout_sec": 600 });
+        let mut config = with_timeouts(json);
+        assert!(cap_run_timeout(&mut config, 3600, false).is_ok());
+
+        // Run timeout already bounds all attempts together
+        let json = serde_json::json!({ "run_timeout_sec": 60, "restart_policy": "always", "restart_timeout_scope": "total" });
+        let mut config = with_timeouts(json);
+        assert!(cap_run_timeout(&mut config, 3600, false).is_ok());
+    }
+
+    #[test]
+    fn output_outside_root_is_rejected() {
+        let root = env::temp_dir().join("runner-unit-output-root");
+        fs::create_dir_all(&root).unwrap();
+        let root = root.to_string_lossy().into_owned();
+
+        let config = with_timeouts(serde_json::json!({
+            "streams": { "combined": format!("{root}/not/yet/created.log") },
+        }));
+        assert!(validate_output_root(&config, &root).is_ok());
+
+        for path in [format!("{root}/../escaped.log"), format!("{root}/a/../../escaped.log")] {
+            let config = with_timeouts(serde_json::json!({
+                "streams": { "stdout": { "type": "file", "path": path } },
+            }));
+            assert!(validate_output_root(&config, &root).is_err());
+        }
+    }
+
+    #[test]
+    fn paths_inside_private_tmp_are_rejected() {
+        let config = with_timeouts(serde_json::json!({
+            "cwd": "/",
+            "command": ["/tmp/fuzzer"],
+            "run_timeout_sec": 60,
+            "private_tmp": true,
+        }));
+        assert_eq!(validate(&config).unwrap_err(), "'/tmp/fuzzer' can not be inside /tmp with private_tmp");
+
+        let config = with_timeouts(serde_json::json!({
+            "cwd": "/",
+            "streams": { "combined": "/tmp/run/out.log" },
+            "run_timeout_sec": 60,
+            "private_tmp": true,
+        }));
+        assert_eq!(validate(&config).unwrap_err(), "'/tmp/run/out.log' can not be inside /tmp with private_tmp");
+    }
+
+    #[test]
+    fn json5_configs_are_parsed() {
+        let content = "{\n  // comment\n  cwd: '/tmp', /* block */ run_timeout_ms: 0x3e8,\n  command: ['true',],\n}";
+        let parsed: Value = serde_json::from_str(&json5_to_json(content).unwrap()).unwrap();
+        assert_eq!(parsed, serde_json::json!({ "cwd": "/tmp", "run_timeout_ms": 1000, "command": ["true"] }));
+
+        assert!(json5_to_json("{ a: 1 /* never closed").is_err());
+    }
+
+    #[test]
+    fn comments_are_allowed_by_extension() {
+        let dir = env::temp_dir().join("runner-unit-json5");
+        fs::create_dir_all(&dir).unwrap();
+
+        for (name, allowed) in [("run.json5", true), ("run.jsonc", true), ("run.json", false)] {
+            let path = dir.join(name).to_string_lossy().into_owned();
+            fs::write(&path, "{ // comment\n \"a\": 1 }").unwrap();
+
+            let parsed = serde_json::from_str::<Value>(&read_config(&path).unwrap());
+            assert_eq!(parsed.is_ok(), allowed, "{name}");
+        }
+    }
+
+    #[test]
+    fn huge_timeout_is_kept() {
+        let config = with_timeouts(serde_json::json!({ "run_timeout_sec": u64::MAX }));
+        assert_eq!(effective_timeout(&config), Some(Duration::from_secs(u64::MAX)));
+    }
+
+    #[test]
+    fn top_level_max_open_files_is_rejected() {
+        let config = with_timeouts(serde_json::json!({ "max_open_files": 1024 }));
+        assert!(validate(&config).unwrap_err().contains("limits.max_open_files"));
+    }
+
+    #[test]
+    fn env_errors_point_into_the_form_used() {
+        let parse = |env: Value| {
+            serde_json::from_value::<Config>(serde_json::json!({
+                "cwd": "/tmp",
+                "command": ["true"],
+                "env": env,
+                "streams": {},
+                "poll_interval_ms": 100,
+                "grace_period_sec": 1,
+            }))
+        };
+
+        let config = parse(serde_json::json!({ "A": "1" })).unwrap();
+        assert_eq!((config.env[0].name.as_str(), config.env[0].value.as_str()), ("A", "1"));
+
+        let e = parse(serde_json::json!([{ "name": "A" }])).err().unwrap().to_string();
+        assert!(e.contains("missing field `value`"), "{e}");
+
+        let e = parse(serde_json::json!({ "A": 1 })).err().unwrap().to_string();
+        assert!(e.contains("invalid type: integer `1`"), "{e}");
+
+        let e = parse(serde_json::json!("A=1")).err().unwrap().to_string();
+        assert!(e.contains("env must be a list"), "{e}");
+    }
+
+    #[test]
+    fn tagged_objects_are_replaced_by_overlay() {
+        let global = serde_json::json!({
+            "streams": {
+                "stdout": { "type": "pipe" },
+                "stderr": { "type": "file", "path": "/var/log/global.log", "mode": "append" },
+            },
+        });
+        let overlay = serde_json::json!({
+            "streams": {
+                "stdout": { "type": "file", "path": "/tmp/out.log" },
+                "stderr": { "type": "pipe" },
+            },
+        });
+
+        let merged = merge_global(global, overlay);
+        assert_eq!(merged["streams"]["stdout"], serde_json::json!({ "type": "file", "path": "/tmp/out.log" }));
+        assert_eq!(merged["streams"]["stderr"], serde_json::json!({ "type": "pipe" }));
+    }
+}
@@ -0,0 +1,46 @@
+use std::fs::OpenOptions;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+//
+// Runner's own messages go to its stderr. With
+// runner_log_file fd 2 is pointed to that file, and
+// the original stderr is kept aside for the child
+// streams that inherit it.
+//
+
+static CHILD_STDERR: AtomicI32 = AtomicI32::new(-1);
+
+pub fn redirect(path: &str) -> Result<(), String> {
+    let file = match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to open runner log file. Path: {path}. Reason - {e}")),
+    };
+
+    let saved = unsafe { libc::fcntl(libc::STDERR_FILENO, libc::F_DUPFD_CLOEXEC, 0) };
+    if saved < 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(format!("Failed to duplicate stderr. Reason - {e}"));
+    }
+
+    if unsafe { libc::dup2(file.as_raw_fd(), libc::STDERR_FILENO) } < 0 {
+        let e = std::io::Error::last_os_error();
+        unsafe { libc::close(saved) };
+        return Err(format!("Failed to redirect stderr. Reason - {e}"));
+    }
+
+    CHILD_STDERR.store(saved, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn is_redirected() -> bool {
+    CHILD_STDERR.load(Ordering::Relaxed) >= 0
+}
+
+// Stderr as it was before redirect, for the child to inherit
+pub fn child_stderr() -> RawFd {
+    match CHILD_STDERR.load(Ordering::Relaxed) {
+        fd if fd >= 0 => fd,
+        _ => libc::STDERR_FILENO,
+    }
+}
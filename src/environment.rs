@@ -0,0 +1,147 @@
+use crate::config::Config;
+use crate::config::EnvironmentalVariableMode;
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+
+pub type Environment = HashMap<OsString, OsString>;
+
+const SECRET_PATTERNS: [&str; 6] = [
+    "SECRET",
+    "TOKEN",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "KEY",
+];
+
+pub fn build(config: &Config) -> Environment {
+    let mut tmp_envs = HashMap::new();
+
+    for (key, value) in env::vars_os() {
+        if let Some(allowlist) = &config.inherit_env_allowlist {
+            if !allowlist.iter().any(|name| key == name.as_str()) {
+                continue;
+            }
+        }
+
+        tmp_envs.insert(key, value);
+    }
+
+    for env in config.env.iter() {
+        let name = Into::<OsString>::into(env.name.clone());
+        let value = match env.raw.unwrap_or(!config.expand_env) {
+            true => Into::<OsString>::into(env.value.clone()),
+            false => Into::<OsString>::into(expand(&env.value, &tmp_envs)),
+        };
+
+        let value = match (env.mode, tmp_envs.get(&name)) {
+            (EnvironmentalVariableMode::Prepend, Some(old)) if !old.is_empty() => {
+                let mut joined = value;
+                joined.push(&env.separator);
+                joined.push(old);
+                joined
+            }
+            (EnvironmentalVariableMode::Append, Some(old)) if !old.is_empty() => {
+                let mut joined = old.clone();
+                joined.push(&env.separator);
+                joined.push(value);
+                joined
+            }
+            _ => value,
+        };
+
+        tmp_envs.insert(name, value);
+    }
+
+    tmp_envs
+}
+
+//
+// Replaces ${NAME} with value of the variable as it is
+// at that point: inherited or set by an earlier entry.
+// Unknown variables expand to empty string, anything
+// else including lone $ and unclosed ${ is kept as is.
+//
+
+fn expand(value: &str, envs: &Environment) -> String {
+    let mut expanded = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+
+        let name = &rest[start + 2..start + 2 + len];
+        expanded.push_str(&rest[..start]);
+        if let Some(val) = envs.get(&OsString::from(name)) {
+            expanded.push_str(&val.to_string_lossy());
+        }
+
+        rest = &rest[start + 3 + len..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_uppercase();
+    SECRET_PATTERNS.iter().any(|p| name.contains(p))
+}
+
+pub fn print(envs: &Environment) {
+    let mut entries: Vec<_> = envs.iter().collect();
+    entries.sort();
+
+    eprintln!("Environment:");
+    for (name, value) in entries {
+        let name = name.to_string_lossy();
+        if is_secret(&name) {
+            eprintln!("  {name}=<redacted>");
+        } else {
+            eprintln!("  {name}={}", value.to_string_lossy());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envs() -> Environment {
+        let mut envs = Environment::new();
+        envs.insert(OsString::from("HOME"), OsString::from("/root"));
+        envs.insert(OsString::from("EMPTY"), OsString::new());
+        envs
+    }
+
+    #[test]
+    fn braced_variables_are_expanded() {
+        assert_eq!(expand("${HOME}/bin", &envs()), "/root/bin");
+        assert_eq!(expand("${HOME}:${HOME}", &envs()), "/root:/root");
+        assert_eq!(expand("[${EMPTY}]", &envs()), "[]");
+    }
+
+    #[test]
+    fn undefined_variables_are_empty() {
+        assert_eq!(expand("${UNDEFINED}/bin", &envs()), "/bin");
+        assert_eq!(expand("${}", &envs()), "");
+    }
+
+    #[test]
+    fn bare_dollars_are_kept() {
+        assert_eq!(expand("$HOME/bin", &envs()), "$HOME/bin");
+        assert_eq!(expand("$$", &envs()), "$$");
+        assert_eq!(expand("$${HOME}", &envs()), "$/root");
+        assert_eq!(expand("cost: 5$", &envs()), "cost: 5$");
+    }
+
+    #[test]
+    fn unterminated_braces_are_kept() {
+        assert_eq!(expand("${HOME", &envs()), "${HOME");
+        assert_eq!(expand("${HOME}/${HOME", &envs()), "/root/${HOME");
+    }
+}
@@ -0,0 +1,38 @@
+use crate::timestamp;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+//
+// Lifecycle events as JSON Lines on stderr.
+// Human readable messages are printed as before,
+// each event takes a whole line of its own.
+//
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize)]
+struct Event<'a> {
+    event: &'a str,
+    #[serde(flatten)]
+    fields: Value,
+    ts: f64,
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn emit(event: &str, fields: Value) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let event = Event { event, fields, ts: timestamp::now_precise() };
+    match serde_json::to_string(&event) {
+        Ok(val) => eprintln!("{val}"),
+        Err(e) => eprintln!("Failed to serialize event. Reason - {e}"),
+    }
+}
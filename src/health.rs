@@ -0,0 +1,70 @@
+use crate::config::Config;
+use crate::hook;
+
+use std::time::{Duration, Instant};
+
+//
+// Periodic liveness probe of the child. Each check
+// is bounded by its own timeout, so a hung probe
+// delays the wait loop by the timeout at most.
+//
+
+pub struct HealthCheck {
+    argv: Vec<String>,
+    interval: Duration,
+    timeout: Duration,
+    max_failures: u32,
+    failures: u32,
+    next: Instant,
+}
+
+impl HealthCheck {
+    pub fn new(config: &Config) -> Option<Self> {
+        let argv = config.health_check_command.clone()?;
+        let interval = Duration::from_secs(config.health_check_interval_sec);
+
+        Some(Self {
+            argv,
+            interval,
+            timeout: Duration::from_secs(config.health_check_timeout_sec),
+            max_failures: config.health_check_failures,
+            failures: 0,
+            next: Instant::now() + interval,
+        })
+    }
+
+    pub fn next(&self) -> Instant {
+        self.next
+    }
+
+    //
+    // Runs the check if it is due. Returns true
+    // once failures in a row reach the limit.
+    //
+
+    pub fn poll(&mut self, pid: Option<u32>) -> bool {
+        if Instant::now() < self.next {
+            return false;
+        }
+
+        let pid = pid.map(|val| val.to_string()).unwrap_or_default();
+        let envs = [("SBXBIN_CHILD_PID", pid)];
+
+        let healthy = match hook::run(&self.argv, &envs, self.timeout) {
+            Ok(status) if status.success() => true,
+            Ok(status) => {
+                eprintln!("Health check failed: {status:?}");
+                false
+            }
+            Err(e) => {
+                eprintln!("Health check failed. Reason - {e}");
+                false
+            }
+        };
+
+        self.failures = if healthy { 0 } else { self.failures + 1 };
+        self.next = Instant::now() + self.interval;
+
+        self.failures >= self.max_failures
+    }
+}
@@ -0,0 +1,43 @@
+use crate::redirection::dup_fd;
+
+use subprocess::{ExitStatus, Popen, PopenConfig, Redirection};
+
+use std::ffi::OsString;
+use std::time::Duration;
+
+//
+// Short auxiliary commands run by the runner itself.
+// Their output goes to runner's stderr, so stdout
+// stays reserved for the result.
+//
+
+pub fn run(argv: &[String], envs: &[(&str, String)], timeout: Duration) -> Result<ExitStatus, String> {
+    let mut env = PopenConfig::current_env();
+    for (name, value) in envs {
+        env.push((OsString::from(name), OsString::from(value)));
+    }
+
+    let pconf = PopenConfig {
+        stdin: Redirection::None,
+        stdout: dup_fd(libc::STDERR_FILENO)?,
+        env: Some(env),
+        ..Default::default()
+    };
+
+    let mut ps = match Popen::create(argv, pconf) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to start '{}'. Reason - {e}", argv.join(" "))),
+    };
+
+    match ps.wait_timeout(timeout) {
+        Ok(Some(val)) => return Ok(val),
+        Ok(None) => {}
+        Err(e) => return Err(format!("Unhandled error in process.wait(): {e}")),
+    }
+
+    // Hook must never stall the caller
+    let _ = ps.kill();
+    let _ = ps.wait();
+
+    Err(format!("'{}' timed out after {} sec", argv.join(" "), timeout.as_secs()))
+}
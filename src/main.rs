@@ -1,20 +1,60 @@
+mod archive;
+mod command;
+mod capture;
 mod config;
+mod diagnostics;
+mod environment;
+mod events;
+mod health;
+mod hook;
+mod output;
+mod preexec;
+mod procstatus;
+mod pty;
+mod publish;
 mod redirection;
+mod regex;
+mod reopen;
+mod restart;
+mod rusage;
+mod selftest;
+mod server;
+mod sha256;
+mod signals;
+mod spawn;
+mod result;
+mod rotate;
+mod termination;
+mod triage;
+mod timestamp;
+mod uuid;
+mod wakeup;
 
-use config::Config;
+use config::{Config, RestartPolicy, TimeoutScope};
+use health::HealthCheck;
+use output::{FileCounter, OutputProxy};
+use preexec::PreExec;
+use procstatus::PeakTracker;
+use restart::RestartLimiter;
+use result::{ExitReason, PollStats, RunResult, ShutdownTrigger};
+use termination::Termination;
+use wakeup::Wakeup;
 
-use signal_hook::flag::register;
+use serde_json::json;
 
+use subprocess::unix::PopenExt;
 use subprocess::ExitStatus;
 use subprocess::Popen;
 use subprocess::PopenConfig;
+use subprocess::Redirection;
 
-use std::collections::HashMap;
 use std::env;
-use std::ffi::OsString;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 #[rustfmt::skip]
 use redirection::{
@@ -23,55 +63,142 @@ use redirection::{
     stderr_redirection,
 };
 
-#[derive(Debug)]
-enum ExitReason {
-    Finished,
-    Timeout,
-    Terminated,
-    InternalError,
+fn exit(reason: ExitReason) -> ! {
+    std::process::exit(reason.default_code())
 }
 
-fn exit(reason: ExitReason) -> ! {
-    match reason {
-        ExitReason::Finished => std::process::exit(0),
-        ExitReason::Timeout => std::process::exit(138), // SIGUSR1
-        ExitReason::Terminated => std::process::exit(130), // SIGTERM
-        ExitReason::InternalError => std::process::exit(-1),
+enum Mode {
+    // Config path and whether to print summary
+    Run(String, bool),
+    Serve(String, Option<u64>),
+    Validate(String),
+    SelfTest,
+}
+
+fn get_mode() -> Mode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let mut args = args.as_slice();
+    let mut summary = false;
+
+    loop {
+        args = match args {
+            ["--log-format", "json", rest @ ..] => {
+                events::enable();
+                rest
+            }
+            ["--log-format", "text", rest @ ..] => rest,
+            ["--summary", rest @ ..] => {
+                summary = true;
+                rest
+            }
+            _ => break,
+        };
+    }
+
+    match args {
+        ["--serve", socket_path] => {
+            eprintln!("Serving on socket: '{socket_path}'");
+            Mode::Serve(socket_path.to_string(), None)
+        }
+        ["--serve", socket_path, "--max-jobs", max_jobs] if max_jobs.parse::<u64>().is_ok() => {
+            eprintln!("Serving on socket: '{socket_path}', at most {max_jobs} jobs");
+            Mode::Serve(socket_path.to_string(), max_jobs.parse().ok())
+        }
+        ["validate", config_path] => {
+            eprintln!("Validating config file: '{config_path}'");
+            Mode::Validate(config_path.to_string())
+        }
+        ["selftest"] => {
+            eprintln!("Running self-test");
+            Mode::SelfTest
+        }
+        // Child spawned by the self-test
+        ["selftest", selftest::CHILD_ARG] => selftest::sleep_forever(),
+        [config_path] if !config_path.starts_with("--") => {
+            eprintln!("Using config file: '{config_path}'");
+            Mode::Run(config_path.to_string(), summary)
+        }
+        _ => {
+            eprintln!("Usage: monitor [--log-format text|json] [--summary] <config.json>");
+            eprintln!("       monitor [--log-format text|json] validate <config.json>");
+            eprintln!("       monitor [--log-format text|json] --serve <socket> [--max-jobs <n>]");
+            eprintln!("       monitor [--log-format text|json] selftest");
+            exit(ExitReason::InternalError);
+        }
     }
 }
 
-fn get_config_path() -> String {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: monitor <config.json>");
-        exit(ExitReason::InternalError);
+fn validate_config(path: &str) -> ! {
+    let problems = match config::load_json(path) {
+        Ok(config) => config::check_host(&config),
+        Err(e) => vec![e],
+    };
+
+    if problems.is_empty() {
+        eprintln!("Config is valid");
+        exit(ExitReason::Finished);
     }
 
-    let config_path = args[1].clone();
-    eprintln!("Using config file: '{config_path}'");
-    config_path
+    eprintln!("Config is invalid:");
+    for problem in problems {
+        eprintln!("  - {problem}");
+    }
+
+    exit(ExitReason::ConfigError);
 }
 
 fn get_config(path: &str) -> Config {
     config::load_json(path).unwrap_or_else(|e| {
         eprintln!("Failed to load config. Reason - {e}");
-        exit(ExitReason::InternalError);
+        exit(ExitReason::ConfigError);
     })
 }
 
-fn create_popen_config(config: &Config) -> Result<PopenConfig, String> {
+fn check_env(config: &Config) -> Result<(), String> {
+    if config.require_nonempty_env {
+        if let Some(env) = config.env.iter().find(|env| env.value.is_empty()) {
+            return Err(format!("Environment variable '{}' is empty", env.name));
+        }
+    }
 
-    let mut tmp_envs = HashMap::new();
+    Ok(())
+}
 
-    for (key, value) in env::vars_os() {
-        tmp_envs.insert(key, value);
+// Bare program names are looked up in it by exec
+fn child_path(pconf: &PopenConfig) -> Option<&OsStr> {
+    let envs = pconf.env.as_ref()?;
+    envs.iter().find(|(name, _)| name == "PATH").map(|(_, value)| value.as_os_str())
+}
+
+fn create_popen_config(config: &Config, run_id: Option<&str>) -> Result<PopenConfig, String> {
+    let mut tmp_envs = environment::build(config);
+
+    if let (Some(name), Some(id)) = (&config.inject_run_id, run_id) {
+        tmp_envs.insert(name.into(), id.into());
+    }
+
+    // Lets cooperative targets align their own watchdogs
+    if config.inject_timeout_env {
+        let grace = shutdown_interval(config).saturating_mul(config.sigterm_attempts.max(1));
+        let run_timeout = config::effective_timeout(config);
+        let timeouts = [
+            ("SBXBIN_RUN_TIMEOUT_SEC", run_timeout.map(|val| val.as_secs())),
+            ("SBXBIN_RUN_TIMEOUT_MS", run_timeout.map(|val| u64::try_from(val.as_millis()).unwrap_or(u64::MAX))),
+            ("SBXBIN_GRACE_PERIOD_SEC", Some(grace.as_secs())),
+        ];
+
+        // Disabled run timeout is not set at all
+        for (name, value) in timeouts {
+            if let Some(value) = value {
+                tmp_envs.insert(name.into(), value.to_string().into());
+            }
+        }
     }
 
-    for env in config.env.iter() {
-        tmp_envs.insert(
-            Into::<OsString>::into(env.name.clone()),
-            Into::<OsString>::into(env.value.clone()),
-        );
+    if config.verbose {
+        environment::print(&tmp_envs);
     }
 
     let list_of_tuples_env = tmp_envs.iter()
@@ -84,9 +211,9 @@ fn create_popen_config(config: &Config) -> Result<PopenConfig, String> {
         .collect();
 
     Ok(PopenConfig {
-        stdin: stdin_redirection(&config)?,
-        stdout: stdout_redirection(&config)?,
-        stderr: stderr_redirection(&config)?,
+        stdin: stdin_redirection(config)?,
+        stdout: stdout_redirection(config)?,
+        stderr: stderr_redirection(config)?,
         env: Some(list_of_tuples_env),
         cwd: Some(config.cwd.clone().into()),
         ..Default::default()
@@ -101,44 +228,178 @@ fn get_exit_code(exit_status: ExitStatus) -> Option<u32> {
     }
 }
 
-#[rustfmt::skip]
-fn graceful_shutdown(ps: &mut Popen, timeout: Duration) -> Result<u32, String> {
-    //
-    // Send SIGTERM and hope the process
-    // will handle it and exit normally
-    //
+//
+// Acceptance check run after the child has finished,
+// e.g. to look for an expected artifact. Returns
+// whether it has passed and its exit code.
+//
 
-    if let Err(e) = ps.terminate() {
-        return Err(format!(
-            "Failed to send SIGTERM to {:?}. Reason - {}",
-            ps.pid(), e.to_string()
-        ));
-    }
+fn run_verify(
+    argv: &[String],
+    config: &Config,
+    child_exit_code: Option<u32>,
+    run_id: Option<&str>,
+    timeout: Duration,
+) -> (bool, Option<u32>) {
+    eprintln!("Run verify command: '{}'", argv.join(" "));
 
-    //
-    // Give some time to shutdown
-    //
+    let envs = [
+        ("SBXBIN_CHILD_EXIT_CODE", child_exit_code.map(|code| code.to_string()).unwrap_or_default()),
+        ("SBXBIN_RUN_ID", run_id.unwrap_or_default().to_string()),
+        ("SBXBIN_CWD", config.cwd.clone()),
+    ];
 
-    let result = match ps.wait_timeout(timeout) {
-        Ok(val) => val,
+    match hook::run(argv, &envs, timeout) {
+        Ok(status) => {
+            eprintln!("Verify command finished: {status:?}");
+            (status.success(), get_exit_code(status))
+        }
         Err(e) => {
+            eprintln!("Verify command failed. Reason - {e}");
+            (false, None)
+        }
+    }
+}
+
+//
+// Diagnostic command run right before SIGKILL,
+// e.g. to dump stacks of a stuck child
+//
+
+fn run_pre_kill(argv: &[String], pid: Option<u32>, timeout_sec: u64) {
+    let pid = match pid {
+        Some(val) => val.to_string(),
+        None => return,
+    };
+
+    eprintln!("Run pre-kill command: '{}'", argv.join(" "));
+
+    let envs = [("SBXBIN_CHILD_PID", pid)];
+    match hook::run(argv, &envs, Duration::from_secs(timeout_sec)) {
+        Ok(status) => eprintln!("Pre-kill command finished: {status:?}"),
+        Err(e) => eprintln!("Pre-kill command failed. Reason - {e}"),
+    }
+}
+
+// Time to wait after each shutdown signal
+fn shutdown_interval(config: &Config) -> Duration {
+    Duration::from_secs(config.sigterm_interval_sec.unwrap_or(config.grace_period_sec))
+}
+
+//
+// Reaps the child only once its resource usage is taken,
+// which is gone after the reap. Plain wait is the fallback
+// if the usage can not be looked at.
+//
+
+fn try_reap(ps: &mut Popen, max_rss_kb: &mut Option<u64>) -> subprocess::Result<Option<ExitStatus>> {
+    if let Some(pid) = ps.pid() {
+        match rusage::max_rss_kb(pid, false) {
+            Ok(Some(val)) => *max_rss_kb = Some(val),
+            Ok(None) => return Ok(None),
+            Err(_) => {}
+        }
+    }
+
+    ps.wait_timeout(Duration::ZERO)
+}
+
+fn reap(ps: &mut Popen, max_rss_kb: &mut Option<u64>) -> subprocess::Result<ExitStatus> {
+    if let Some(Ok(Some(val))) = ps.pid().map(|pid| rusage::max_rss_kb(pid, true)) {
+        *max_rss_kb = Some(val);
+    }
+
+    ps.wait()
+}
+
+//
+// Waits up to interval for the child to exit after a
+// shutdown signal. First check happens after settle
+// time, then every shutdown poll until the interval
+// expires, so an early exit is reaped right away.
+//
+
+fn wait_exit(ps: &mut Popen, interval: Duration, config: &Config, max_rss_kb: &mut Option<u64>) -> subprocess::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + interval;
+    let settle = Duration::from_millis(config.shutdown_settle_ms).min(interval);
+    let poll = Duration::from_millis(config.shutdown_poll_ms);
+
+    thread::sleep(settle);
+
+    loop {
+        if let Some(status) = try_reap(ps, max_rss_kb)? {
+            return Ok(Some(status));
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+
+        thread::sleep(poll.min(remaining));
+    }
+}
+
+//
+// Returns exit status of the child and whether
+// the runner has sent SIGKILL to get it
+//
+
+#[rustfmt::skip]
+fn graceful_shutdown(ps: &mut Popen, config: &Config, max_rss_kb: &mut Option<u64>) -> Result<(ExitStatus, bool), String> {
+    let interval = shutdown_interval(config);
+
+    let signal = config.shutdown_signal.0;
+    let mut result = None;
+
+    for attempt in 1..=config.sigterm_attempts.max(1) {
+        //
+        // Send shutdown signal (SIGTERM by default) and
+        // hope the process will handle it and exit normally
+        //
+
+        if let Err(e) = ps.send_signal(signal) {
             return Err(format!(
-                "Unhandled error in process.wait(): {}",
-                e.to_string()
+                "Failed to send {} to {:?}. Reason - {}",
+                signals::name(signal), ps.pid(), e
             ));
         }
-    };
+
+        //
+        // Give some time to shutdown
+        //
+
+        result = match wait_exit(ps, interval, config, max_rss_kb) {
+            Ok(val) => val,
+            Err(e) => {
+                return Err(format!(
+                    "Unhandled error in process.wait(): {}",
+                    e
+                ));
+            }
+        };
+
+        if result.is_some() {
+            break;
+        }
+
+        eprintln!("Process ignored {} (attempt {attempt})", signals::name(signal));
+    }
 
     //
     // Process has ignored SIGTERM
     // Send SIGKILL to finish it off
     //
 
-    if let None = result {
+    if let (None, Some(argv)) = (&result, &config.pre_kill_command) {
+        run_pre_kill(argv, ps.pid(), config.pre_kill_timeout_sec);
+    }
+
+    if result.is_none() {
         if let Err(e) = ps.kill() {
             return Err(format!(
                 "Failed to send SIGKILL to {:?}. Reason - {}",
-                ps.pid(), e.to_string()
+                ps.pid(), e
             ));
         }
     }
@@ -148,140 +409,575 @@ fn graceful_shutdown(ps: &mut Popen, timeout: Duration) -> Result<u32, String> {
     // Just wait a bit and get exit code
     //
 
-    let result = match ps.wait() {
-        Ok(val) => val,
+    // Shutdown signal may be SIGKILL as well
+    let killed = result.is_none() || signal == libc::SIGKILL;
+
+    match reap(ps, max_rss_kb) {
+        Ok(val) => Ok((val, killed)),
         Err(e) => {
-            return Err(format!(
+            Err(format!(
                 "Unhandled error in process.wait(): {}",
-                e.to_string()
-            ));
+                e
+            ))
         }
-    };
+    }
+}
 
-    let exit_code = match get_exit_code(result) {
-        Some(val) => val,
-        None => {
-            return Err(String::from("Failed to get child exit code"));
+//
+// Sleeps in poll sized steps to stay responsive.
+// Returns false if interrupted by term signal.
+//
+
+fn interruptible_sleep(dur: Duration, poll: Duration, term: &Termination) -> bool {
+    let deadline = Instant::now().checked_add(dur);
+
+    loop {
+        if term.is_set() {
+            return false;
         }
+
+        let remaining = match deadline {
+            Some(val) => val.saturating_duration_since(Instant::now()),
+            None => poll,
+        };
+
+        if remaining.is_zero() {
+            return true;
+        }
+
+        thread::sleep(poll.min(remaining));
+    }
+}
+
+//
+// Wall clock time of a past monotonic instant,
+// lets timeouts be ordered against signals
+//
+
+fn wall_time(instant: Instant) -> f64 {
+    timestamp::now_precise() - Instant::now().saturating_duration_since(instant).as_secs_f64()
+}
+
+//
+// Child started with start_stopped stops itself right
+// before exec. Waits until it is stopped and continued
+// again, e.g. by a debugger, or exits. Returns false if
+// interrupted by term signal.
+//
+
+fn wait_continued(ps: &mut Popen, poll: Duration, term: &Termination, wakeup: &Wakeup, max_rss_kb: &mut Option<u64>) -> bool {
+    let pid = match ps.pid() {
+        Some(val) => val,
+        None => return true,
     };
 
-    Ok(exit_code)
+    let mut stopped = false;
+
+    loop {
+        // Failed pre-exec or killed child is handled as usual
+        if !matches!(try_reap(ps, max_rss_kb), Ok(None)) {
+            return true;
+        }
+
+        let is_stopped = matches!(procstatus::state(pid), Some('T' | 't'));
+        if is_stopped && !stopped {
+            eprintln!("Child is stopped before exec. Send SIGCONT to pid {pid} to start it");
+            events::emit("paused", json!({ "pid": pid }));
+            stopped = true;
+        } else if !is_stopped && stopped {
+            eprintln!("Child is continued");
+            return true;
+        }
+
+        if term.is_set() {
+            return false;
+        }
+
+        wakeup.wait(poll);
+    }
 }
 
-fn main() {
+fn signal_trigger(term: &Termination) -> Option<ShutdownTrigger> {
+    term.signal().map(|(signal, ts)| ShutdownTrigger {
+        reason: ExitReason::Terminated,
+        signal: Some(signals::name(signal)),
+        ts,
+    })
+}
+
+fn run_child(
+    config: &Config,
+    term: &Termination,
+    wakeup: &Wakeup,
+    dur_run: Option<Duration>,
+    budget: Option<Instant>,
+) -> (ExitReason, RunResult) {
     //
     // Run results
     //
 
-    let mut child_exit_code: Option<u32> = None;
+    let mut run_result = RunResult::default();
     let mut exit_reason = ExitReason::Finished;
 
     //
-    // Get config
+    // Setup poll interval, duration...
     //
 
-    let config_path = get_config_path();
-    let config = get_config(&config_path);
+    let dur_poll = Duration::from_millis(config.poll_interval_ms);
 
     //
-    // Register signal handlers
+    // Generate run id to correlate logs and artifacts.
+    // Fixed one is used as is, e.g. to be asserted on.
     //
 
-    let signals = [
-        signal_hook::consts::SIGINT,  // rustfmt::skip
-        signal_hook::consts::SIGTERM, // rustfmt::skip
-    ];
-
-    let term = Arc::new(AtomicBool::new(false));
+    if let Some(id) = &config.run_id {
+        run_result.run_id = Some(id.clone());
+    } else if config.inject_run_id.is_some() {
+        run_result.run_id = match uuid::v4() {
+            Ok(val) => Some(val),
+            Err(e) => {
+                eprintln!("Failed to generate run id. Reason - {e}");
+                return (ExitReason::InternalError, run_result);
+            }
+        };
+    }
 
-    for signal in signals {
-        register(signal, Arc::clone(&term)).unwrap_or_else(|e| {
-            eprintln!("Failed to register signal handlers. Reason - {e}");
-            exit(ExitReason::InternalError);
-        });
+    if let Some(id) = &run_result.run_id {
+        eprintln!("Run id: '{id}'");
     }
 
     //
-    // Setup poll interval, duration...
+    // Let dependencies come up before start
     //
 
-    let poll_interval = config.poll_interval_ms;
-    let mut run_timeout = config.run_timeout_sec * 1000;
-    let dur_timeout = Duration::from_millis(poll_interval);
-    let dur_shutdown = Duration::from_secs(config.grace_period_sec);
+    if let Some(delay) = config.start_delay_sec {
+        eprintln!("Delay start for {delay} sec");
+        let delay = Duration::from_secs(delay);
+        let left = budget.map(|val| val.saturating_duration_since(Instant::now()));
+
+        if !interruptible_sleep(left.map_or(delay, |val| val.min(delay)), dur_poll, term) {
+            eprintln!("Caught SIGTERM during start delay. Exitting...");
+            run_result.shutdown_trigger = signal_trigger(term);
+            return (ExitReason::Terminated, run_result);
+        }
+
+        if left.is_some_and(|val| val < delay) {
+            eprintln!("Total timeout during start delay. Exitting...");
+            return (ExitReason::BudgetExhausted, run_result);
+        }
+    }
 
     //
     // Start process with provided cmdline, cwd, env...
     //
 
-    let pconf = create_popen_config(&config).unwrap_or_else(|e| {
-        eprintln!("Failed to create popen config. Reason - {}", e);
-        exit(ExitReason::InternalError);
-    });
+    if let Err(e) = check_env(config) {
+        eprintln!("Bad environment. Reason - {e}");
+        return (ExitReason::ConfigError, run_result);
+    }
+
+    if let Err(e) = triage::prepare(config) {
+        eprintln!("{e}");
+        return (ExitReason::RedirectionError, run_result);
+    }
+
+    let mut pconf = match create_popen_config(config, run_result.run_id.as_deref()) {
+        Ok(val) => val,
+        Err(e) => {
+            eprintln!("Failed to create popen config. Reason - {}", e);
+            return (ExitReason::RedirectionError, run_result);
+        }
+    };
+
+    let mut argv = match command::select(config, child_path(&pconf)) {
+        Ok((argv, alternative)) => {
+            if let Some(index) = alternative {
+                eprintln!("Using command alternative #{index}");
+            }
+
+            run_result.command_alternative = alternative;
+            argv
+        }
+        Err(e) => {
+            eprintln!("Failed to resolve command. Reason - {e}");
+            return (ExitReason::ConfigError, run_result);
+        }
+    };
+
+    //
+    // Ties the run to an exact binary, missing hash does not
+    // stop the run. Program hashed is the one exec is given,
+    // spawn would search PATH of the runner for a bare name.
+    //
+
+    if config.hash_binary {
+        let digest = match command::find_executable(&argv[0], &config.cwd, child_path(&pconf)) {
+            Some(path) => {
+                let digest = sha256::file(&path);
+                if command::is_bare_name(&argv[0]) {
+                    argv[0] = path.into_os_string();
+                }
+
+                digest
+            }
+            None => Err(format!("'{}' is not found", argv[0].to_string_lossy())),
+        };
+
+        match digest {
+            Ok(val) => {
+                eprintln!("Binary sha256: {val}");
+                run_result.binary_sha256 = Some(val);
+            }
+            Err(e) => eprintln!("Failed to hash binary. Reason - {e}"),
+        }
+    }
+
+    run_result.argv = argv.iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+    run_result.cwd = config.cwd.clone();
 
     eprintln!("Working directory: '{}'", config.cwd);
-    eprintln!("Start process: '{}'", config.command.join(" "));
+    eprintln!("Start process: '{}'", command::display(&argv));
 
-    let mut ps = Popen::create(&config.command, pconf).unwrap_or_else(|e| {
-        eprintln!("Failed to start process. Reason - {}", e.to_string());
-        exit(ExitReason::InternalError);
-    });
+    if config.exec {
+        eprintln!("Exec into target, run timeout is not enforced");
+        let e = preexec::exec(PreExec::from_config(config, argv), pconf);
+        eprintln!("Failed to start process. Reason - {e}");
+        return (ExitReason::SpawnError, run_result);
+    }
+
+    let mut proxy = match OutputProxy::new(config) {
+        Ok(val) => val,
+        Err(e) => {
+            eprintln!("Failed to setup output proxy. Reason - {e}");
+            return (ExitReason::RedirectionError, run_result);
+        }
+    };
+
+    //
+    // Child writes to the pty slave, the runner
+    // reads the master in place of stdout pipe
+    //
+
+    let mut pty_master = None;
+
+    if config.use_pty {
+        let pty = match pty::open(config.pty_rows, config.pty_cols) {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Failed to setup pseudo-terminal. Reason - {e}");
+                return (ExitReason::RedirectionError, run_result);
+            }
+        };
+
+        pconf.stdout = Redirection::File(pty.stdout);
+        pconf.stderr = Redirection::File(pty.stderr);
+        pty_master = Some(pty.master);
+    }
+
+    let (argv, report) = match preexec::wrap(PreExec::from_config(config, argv), &mut pconf) {
+        Ok(val) => val,
+        Err(e) => {
+            eprintln!("Failed to prepare pre-exec hook. Reason - {e}");
+            return (ExitReason::InternalError, run_result);
+        }
+    };
+
+    // Stale ready file from previous run would start the clock immediately
+    if let Some(path) = &config.ready_file {
+        let _ = fs::remove_file(Path::new(&config.cwd).join(path));
+    }
+
+    // Same for stop file, it would stop the run right away
+    let stop_file = config.stop_file.as_ref().map(|path| Path::new(&config.cwd).join(path));
+    if let Some(path) = &stop_file {
+        let _ = fs::remove_file(path);
+    }
+
+    //
+    // Output written straight to files is measured by file growth.
+    // Stderr merged into stdout file is accounted in stdout.
+    //
+
+    let stdout_file = match pconf.stdout {
+        Redirection::File(_) => FileCounter::new(&config.streams.stdout),
+        _ => None,
+    };
+
+    let stderr_file = match pconf.stderr {
+        Redirection::File(_) => FileCounter::new(&config.streams.stderr),
+        _ => None,
+    };
+
+    // Stopped child execs only after it is continued
+    let (report, late_report) = match config.start_stopped {
+        true => (None, report),
+        false => (report, None),
+    };
+
+    let start_timeout = config.start_timeout_sec.map(Duration::from_secs);
+
+    let (mut ps, step_error) = match spawn::spawn(argv, pconf, report, start_timeout) {
+        Ok(Some(val)) => val,
+        Ok(None) => {
+            eprintln!("Process has not started in {} sec. Exitting...", config.start_timeout_sec.unwrap_or(0));
+            return (ExitReason::StartTimeout, run_result);
+        }
+        Err(e) => {
+            eprintln!("Failed to start process. Reason - {}", e);
+            return (ExitReason::SpawnError, run_result);
+        }
+    };
+
+    //
+    // Stopped time does not count towards timeouts,
+    // the clock starts once the child is continued
+    //
+
+    if config.start_stopped && !wait_continued(&mut ps, dur_poll, term, wakeup, &mut run_result.max_rss_kb) {
+        eprintln!("Caught SIGTERM while child is stopped. Exitting...");
+        // Other signals are held until the child is continued
+        let _ = ps.kill();
+        let _ = ps.wait();
+        run_result.shutdown_trigger = signal_trigger(term);
+        return (ExitReason::Terminated, run_result);
+    }
+
+    if let Some(e) = step_error.or_else(|| late_report.and_then(|report| report.read())) {
+        eprintln!("Failed to start process. Reason - pre-exec {e}");
+        let _ = ps.wait();
+        return (ExitReason::SpawnError, run_result);
+    }
+
+    let started = Instant::now();
+    run_result.started_at = Some(timestamp::rfc3339(SystemTime::now()));
+    events::emit("started", json!({ "pid": ps.pid() }));
+    if let Some(master) = pty_master {
+        ps.stdout = Some(master);
+    }
+
+    proxy.start(&mut ps);
 
     //
     // Wait for process finish, run timeout, os signals...
     //
 
+    let mut child_status: Option<ExitStatus> = None;
+    let mut killed_by_runner = false;
+
+    // Absurdly large timeouts do not fit into Instant, treat them as infinite
+    let mut deadline = dur_run.and_then(|val| Instant::now().checked_add(val));
+
+    //
+    // With ready file the run timeout starts
+    // only after the child reports readiness
+    //
+
+    let mut ready_file = config.ready_file.as_ref().map(|path| Path::new(&config.cwd).join(path));
+    let mut ready_deadline = None;
+
+    if ready_file.is_some() {
+        ready_deadline = Instant::now().checked_add(Duration::from_secs(config.ready_timeout_sec));
+        deadline = None;
+    }
+
+    //
+    // Adaptive polling backs off while the child
+    // runs quietly and resets on child output
+    //
+
+    let dur_poll_max = Duration::from_millis(config.adaptive_poll_max_ms).max(dur_poll);
+    let mut poll = dur_poll;
+
+    let mut proc_status = match (config.sample_proc_status, ps.pid()) {
+        (true, Some(pid)) => Some(PeakTracker::new(pid)),
+        _ => None,
+    };
+
+    let mut health_check = HealthCheck::new(config);
+    let mut poll_stats = PollStats::default();
+
     loop {
-        let result = match ps.wait_timeout(dur_timeout) {
+        poll_stats.iterations += 1;
+
+        let active_deadline = match ready_file {
+            Some(_) => ready_deadline,
+            None => deadline,
+        };
+
+        let wait_time = match active_deadline {
+            Some(val) => poll.min(val.saturating_duration_since(Instant::now())),
+            None => poll,
+        };
+
+        let wait_time = match &health_check {
+            Some(check) => wait_time.min(check.next().saturating_duration_since(Instant::now())),
+            None => wait_time,
+        };
+
+        let wait_time = match budget {
+            Some(val) => wait_time.min(val.saturating_duration_since(Instant::now())),
+            None => wait_time,
+        };
+
+        let wait_start = Instant::now();
+        wakeup.wait(wait_time);
+
+        // Zero timeout makes it a single non-blocking waitpid
+        let result = try_reap(&mut ps, &mut run_result.max_rss_kb);
+        poll_stats.wait_sec += wait_start.elapsed().as_secs_f64();
+
+        // Last value tells how close the loop has come to the deadline
+        let now = Instant::now();
+        poll_stats.timeout_remaining_sec = deadline.map(|val| match val.checked_duration_since(now) {
+            Some(left) => left.as_secs_f64(),
+            None => -now.duration_since(val).as_secs_f64(),
+        });
+
+        let result = match result {
             Ok(val) => val,
             Err(e) => {
-                eprintln!("Unhandled error in process.wait(): {}", e.to_string());
+                eprintln!("Unhandled error in process.wait(): {}", e);
                 exit_reason = ExitReason::InternalError;
                 break;
             }
         };
 
         if let Some(exit_status) = result {
-            child_exit_code = match get_exit_code(exit_status) {
-                Some(val) => Some(val),
-                None => {
-                    eprintln!("Failed to get child exit code");
-                    exit_reason = ExitReason::InternalError;
-                    break;
-                }
-            };
-
+            child_status = Some(exit_status);
             break;
         }
 
+        if let Some(tracker) = &mut proc_status {
+            tracker.sample();
+        }
+
+        // Taken every poll, as the child may exit before the next one
+        if let (true, Some(pid)) = (config.sample_smaps_rollup, ps.pid()) {
+            if let Some(rollup) = procstatus::smaps_rollup(pid) {
+                run_result.smaps_rollup = Some(rollup);
+            }
+        }
+
+        if let (Some(_), Some(pid)) = (config.limits.max_threads, ps.pid()) {
+            if let Some(threads) = procstatus::threads(pid) {
+                run_result.peak_threads = run_result.peak_threads.max(Some(threads));
+            }
+        }
+
+        if let (Some(_), Some(pid)) = (config.limits.max_open_files, ps.pid()) {
+            if let Some(files) = procstatus::open_files(pid) {
+                run_result.peak_open_files = run_result.peak_open_files.max(Some(files));
+            }
+        }
+
+        //
+        // Several shutdown causes may be pending at once,
+        // e.g. a signal arriving right at the deadline.
+        // The one that has happened first wins.
+        //
+
+        let mut triggers = Vec::new();
+        let trigger = |reason, ts| ShutdownTrigger { reason, signal: None, ts };
+
+        //
+        // Handle readiness
+        //
+
+        if let Some(path) = &ready_file {
+            if path.exists() {
+                eprintln!("Ready file appeared. Start run timeout");
+                events::emit("ready", json!({}));
+                deadline = dur_run.and_then(|val| Instant::now().checked_add(val));
+                ready_file = None;
+            } else if let Some(val) = ready_deadline.filter(|val| Instant::now() >= *val) {
+                triggers.push(trigger(ExitReason::ReadyTimeout, wall_time(val)));
+            }
+        }
+
         //
         // Handle run timeout
         //
 
-        run_timeout -= poll_interval;
-        if run_timeout <= 0 {
-            eprintln!("Run timeout. Exitting...");
-            child_exit_code = match graceful_shutdown(&mut ps, dur_shutdown) {
-                Ok(val) => Some(val),
-                Err(e) => {
-                    eprintln!("Graceful shutdown failed. Reason - {e}");
-                    exit_reason = ExitReason::InternalError;
-                    break;
-                }
-            };
+        if let Some(val) = deadline.filter(|val| ready_file.is_none() && Instant::now() >= *val) {
+            triggers.push(trigger(ExitReason::Timeout, wall_time(val)));
+        }
 
-            exit_reason = ExitReason::Timeout;
-            break;
+        // Total timeout counts during readiness wait as well
+        if let Some(val) = budget.filter(|val| Instant::now() >= *val) {
+            triggers.push(trigger(ExitReason::BudgetExhausted, wall_time(val)));
+        }
+
+        //
+        // Handle stop request from orchestrator
+        //
+
+        // Seen by the runner, mtime may be set to anything
+        if stop_file.as_ref().is_some_and(|path| path.exists()) {
+            triggers.push(trigger(ExitReason::Stopped, timestamp::now_precise()));
+        }
+
+        //
+        // Handle liveness probe, not before the child is ready
+        //
+
+        if let (Some(check), None) = (&mut health_check, &ready_file) {
+            if check.poll(ps.pid()) {
+                triggers.push(trigger(ExitReason::HealthCheckFailed, timestamp::now_precise()));
+            }
+        }
+
+        //
+        // Handle orchestrator crash, orphaned runner
+        // is reparented to init or a subreaper
+        //
+
+        if let Some(pid) = config.require_parent_pid {
+            if unsafe { libc::getppid() } as u32 != pid {
+                triggers.push(trigger(ExitReason::ParentLost, timestamp::now_precise()));
+            }
         }
 
         //
         // Handle OS signals
         //
 
-        if term.load(Ordering::Relaxed) {
-            eprintln!("Caught SIGTERM. Exitting...");
-            child_exit_code = match graceful_shutdown(&mut ps, dur_shutdown) {
-                Ok(val) => Some(val),
+        triggers.extend(signal_trigger(term));
+
+        if let Some(trigger) = triggers.into_iter().min_by(|a, b| a.ts.total_cmp(&b.ts)) {
+            let reason = trigger.reason;
+            match (reason, &trigger.signal) {
+                (ExitReason::ReadyTimeout, _) => eprintln!("Ready timeout. Exitting..."),
+                (ExitReason::Timeout, _) => eprintln!("Run timeout. Exitting..."),
+                (ExitReason::BudgetExhausted, _) => eprintln!("Total timeout. Exitting..."),
+                (ExitReason::Stopped, _) => eprintln!("Stop file appeared. Exitting..."),
+                (ExitReason::HealthCheckFailed, _) => eprintln!("Child is unhealthy. Exitting..."),
+                (ExitReason::ParentLost, _) => eprintln!("Parent process is gone. Exitting..."),
+                (_, Some(signal)) => eprintln!("Caught {signal}. Exitting..."),
+                _ => {}
+            }
+
+            //
+            // Output of a child stopped while writing it is
+            // likely cut off. Recent means within a poll
+            // interval, but no less than a second.
+            //
+
+            let timed_out = matches!(reason, ExitReason::Timeout | ExitReason::BudgetExhausted);
+            if timed_out || trigger.signal.is_some() {
+                let window = dur_poll.max(Duration::from_secs(1));
+                let mut files = [&stdout_file, &stderr_file].into_iter().flatten();
+                let active = proxy.active_within(window) || files.any(|file| file.written_within(window));
+                run_result.output_truncated_by_timeout = Some(active);
+            }
+
+            events::emit("shutdown", json!({ "reason": reason, "signal": trigger.signal }));
+            run_result.shutdown_trigger = Some(trigger);
+
+            child_status = match graceful_shutdown(&mut ps, config, &mut run_result.max_rss_kb) {
+                Ok((status, killed)) => {
+                    killed_by_runner = killed;
+                    Some(status)
+                }
                 Err(e) => {
                     eprintln!("Graceful shutdown failed. Reason - {e}");
                     exit_reason = ExitReason::InternalError;
@@ -289,17 +985,352 @@ fn main() {
                 }
             };
 
-            exit_reason = ExitReason::Terminated;
+            exit_reason = reason;
             break;
         }
+
+        if config.adaptive_poll {
+            poll = if proxy.take_activity() {
+                dur_poll
+            } else {
+                poll.saturating_mul(2).min(dur_poll_max)
+            };
+        }
+    }
+
+    //
+    // Loop may break on an internal error while the
+    // child is still running. It is shut down the usual
+    // way, and if even that fails, detached instead of
+    // blocking the runner and reported as orphaned.
+    //
+
+    if child_status.is_none() {
+        let reaped = match try_reap(&mut ps, &mut run_result.max_rss_kb) {
+            Ok(Some(status)) => Ok((status, false)),
+            _ => graceful_shutdown(&mut ps, config, &mut run_result.max_rss_kb),
+        };
+
+        match reaped {
+            Ok((status, killed)) => {
+                killed_by_runner = killed;
+                child_status = Some(status);
+                run_result.child_orphaned = Some(false);
+            }
+            Err(e) => {
+                eprintln!("Failed to reap child {:?}, leaving it behind. Reason - {e}", ps.pid());
+                ps.detach();
+                run_result.child_orphaned = Some(true);
+            }
+        }
+    }
+
+    run_result.ended_at = Some(timestamp::rfc3339(SystemTime::now()));
+
+    if config.verbose {
+        run_result.poll_stats = Some(poll_stats);
+    }
+    run_result.duration_sec = Some(started.elapsed().as_secs_f64());
+
+    let child_exit_code = match child_status.map(get_exit_code) {
+        Some(None) => {
+            eprintln!("Failed to get child exit code");
+            exit_reason = ExitReason::InternalError;
+            None
+        }
+        Some(code) => code,
+        None => None,
+    };
+
+    if let Some(ExitStatus::Signaled(signal)) = child_status {
+        run_result.exit_signal = Some(signals::name(signal as i32));
+
+        // SIGKILL may come from the runner or from outside, e.g. OOM killer
+        if signal as i32 == libc::SIGKILL {
+            run_result.killed_by_runner = Some(killed_by_runner);
+        }
+    }
+
+    let drain_timeout = config.streams.drain_timeout_sec.map(Duration::from_secs);
+    if !proxy.drain(drain_timeout) {
+        eprintln!("Output is not drained in {} sec, pipes are held open. Proceeding...", config.streams.drain_timeout_sec.unwrap_or(0));
+        run_result.drain_timed_out = Some(true);
+    }
+
+    let (stdout, stderr) = proxy.finish();
+    run_result.proc_status_peak = proc_status.and_then(|tracker| tracker.peak());
+
+    //
+    // Thread creation fails with EAGAIN at the limit. Other
+    // processes of the uid count too, so the child may fail
+    // below it, and spikes shorter than a poll are missed.
+    //
+
+    if let Some(max_threads) = config.limits.max_threads {
+        let reached = run_result.peak_threads.is_some_and(|peak| peak >= max_threads);
+        if reached {
+            eprintln!("Child has reached max_threads limit of {max_threads}");
+        }
+        run_result.thread_limit_reached = Some(reached);
+    }
+
+    run_result.stdout_bytes = stdout.bytes.or_else(|| stdout_file.and_then(|f| f.bytes()));
+    run_result.stderr_bytes = stderr.bytes.or_else(|| stderr_file.and_then(|f| f.bytes()));
+    run_result.stdout_capture = stdout.capture;
+    run_result.stderr_capture = stderr.capture;
+    run_result.stdout_tail = stdout.tail;
+    run_result.stderr_tail = stderr.tail;
+
+    //
+    // Opening past the limit fails with EMFILE. A failed
+    // child that has been seen at the limit is blamed on
+    // it, descriptors opened and closed within a poll
+    // are missed.
+    //
+
+    if let Some(max_open_files) = config.limits.max_open_files {
+        let reached = run_result.peak_open_files.is_some_and(|peak| peak >= max_open_files);
+        if reached && exit_reason == ExitReason::Finished && !exit_reason.succeeded(config, child_exit_code) {
+            eprintln!("Child has failed at max_open_files limit of {max_open_files}");
+            exit_reason = ExitReason::FileLimit;
+        }
+        run_result.file_limit_reached = Some(reached);
+    }
+
+    //
+    // Child has exited cleanly but reported
+    // a failure on stderr. Other reasons win.
+    //
+
+    if let Some(line) = stderr.matched_line {
+        eprintln!("Stderr matched fail pattern: '{line}'");
+        if exit_reason == ExitReason::Finished {
+            exit_reason = ExitReason::StderrMatched;
+        }
+
+        run_result.stderr_match = Some(line);
+    }
+
+    // Verifier overrides the child's own verdict
+    if let (Some(argv), ExitReason::Finished) = (&config.verify_command, exit_reason) {
+        let timeout = Duration::from_secs(config.verify_timeout_sec);
+        let left = budget.map(|val| val.saturating_duration_since(Instant::now()));
+        let timeout = left.map_or(timeout, |val| val.min(timeout));
+
+        let (passed, code) = run_verify(argv, config, child_exit_code, run_result.run_id.as_deref(), timeout);
+        run_result.verify_exit_code = code;
+
+        if !passed {
+            exit_reason = match budget.is_some_and(|val| Instant::now() >= val) {
+                true => ExitReason::BudgetExhausted,
+                false => ExitReason::VerifyFailed,
+            };
+        }
     }
 
     eprintln!("Exit. Reason: {exit_reason:?}");
     eprintln!("Child exit code: {child_exit_code:?}");
 
-    if let Some(code) = child_exit_code {
-        println!("{}", code);
+    run_result.exit_code = child_exit_code;
+    (exit_reason, run_result)
+}
+
+//
+// Whether the supervisor starts the child again. Only
+// outcomes of the child itself count, the runner's own
+// failures and shutdown requests end the run.
+//
+
+fn should_restart(config: &Config, reason: ExitReason, exit_code: Option<u32>) -> bool {
+    let attempt_timeout = config.restart_timeout_scope == TimeoutScope::Attempt;
+    let child_outcome = match reason {
+        ExitReason::Finished
+        | ExitReason::StderrMatched
+        | ExitReason::FileLimit
+        | ExitReason::VerifyFailed
+        | ExitReason::HealthCheckFailed
+        | ExitReason::ReadyTimeout => true,
+        ExitReason::Timeout => attempt_timeout,
+        _ => false,
+    };
+
+    match config.restart_policy {
+        RestartPolicy::Never => false,
+        RestartPolicy::OnFailure => {
+            // Only codes known to be transient are worth another attempt
+            let retried = config.retry_on_exit_codes.is_empty()
+                || exit_code.is_some_and(|code| config.retry_on_exit_codes.contains(&code));
+            child_outcome && !reason.succeeded(config, exit_code) && retried
+        }
+        RestartPolicy::Always => child_outcome,
     }
+}
+
+fn run_job(config: &Config, term: &Termination, wakeup: &Wakeup) -> RunResult {
+    let mut limiter = RestartLimiter::new(
+        config.max_restarts,
+        Duration::from_secs(config.restart_window_sec),
+    );
+
+    let dur_run = config::effective_timeout(config);
+    let started = Instant::now();
+    let budget = config.total_timeout_sec.and_then(|val| started.checked_add(Duration::from_secs(val)));
+    let mut attempts: u32 = 0;
+    let mut last_result = None;
+
+    //
+    // Supervisor loop, a single pass unless restarts
+    // are enabled. Total timeout is what is left of it
+    // when the attempt starts. Restart limit reports
+    // the last attempt the child has made.
+    //
+
+    let (exit_reason, mut run_result) = loop {
+        let attempt_run = match config.restart_timeout_scope {
+            TimeoutScope::Attempt => dur_run,
+            TimeoutScope::Total => config::remaining_timeout(dur_run, started.elapsed()),
+        };
 
-    exit(exit_reason);
+        if !limiter.try_acquire() {
+            eprintln!("Too many restarts within {} sec", config.restart_window_sec);
+            break (ExitReason::RestartLimit, last_result.unwrap_or_default());
+        }
+
+        let (reason, result) = run_child(config, term, wakeup, attempt_run, budget);
+        attempts += 1;
+
+        let expired = match config.restart_timeout_scope {
+            TimeoutScope::Attempt => false,
+            TimeoutScope::Total => dur_run.is_some_and(|val| started.elapsed() >= val),
+        };
+
+        let exhausted = budget.is_some_and(|val| Instant::now() >= val);
+
+        if term.is_set() || expired || exhausted || !should_restart(config, reason, result.exit_code) {
+            break (reason, result);
+        }
+
+        eprintln!("Child has exited. Restarting...");
+        events::emit("restart", json!({ "reason": reason, "code": result.exit_code }));
+        last_result = Some(result);
+    };
+
+    if config.restart_policy != RestartPolicy::Never {
+        run_result.restarts = Some(attempts.saturating_sub(1));
+    }
+
+    run_result.exit_reason = exit_reason;
+    run_result.metadata = config.metadata.clone();
+    // Report the code as the parent will actually see it
+    let signal = term.signal().map(|(signal, _)| signal);
+    run_result.runner_exit_code = exit_reason.code(config, run_result.exit_code, signal) & 0xff;
+    run_result.succeeded = exit_reason.succeeded(config, run_result.exit_code);
+
+    let event = json!({
+        "reason": exit_reason,
+        "code": run_result.exit_code,
+        "runner_exit_code": run_result.runner_exit_code,
+    });
+
+    events::emit("finished", event);
+
+    if let Some(path) = &config.archive_output {
+        let content = serde_json::to_vec(&run_result).unwrap_or_default();
+        if let Err(e) = archive::create(path, &archive::output_files(config), &content) {
+            eprintln!("{e}");
+        }
+    }
+
+    // Archive has taken the files from pending paths
+    triage::sort(config, exit_reason);
+
+    // Consumers of the result must not decide the exit code
+    if let Some(sink) = &config.result_sink {
+        let json = serde_json::to_string(&run_result).unwrap_or_default();
+        let timeout = Duration::from_secs(config.result_sink_timeout_sec);
+        if let Err(e) = publish::send(sink, &json, timeout) {
+            eprintln!("{e}");
+        }
+    }
+
+    run_result
+}
+
+//
+// Only listed signals trigger shutdown, others
+// keep the disposition inherited by the runner
+//
+
+fn register_signal_handlers(signals: &[i32]) -> (Arc<Termination>, Wakeup) {
+    let term = Termination::register(signals).unwrap_or_else(|e| {
+        eprintln!("Failed to register signal handlers. Reason - {e}");
+        exit(ExitReason::InternalError);
+    });
+
+    let mut wakeup_signals = signals.to_vec();
+    wakeup_signals.push(signal_hook::consts::SIGCHLD);
+
+    let wakeup = Wakeup::new(&wakeup_signals).unwrap_or_else(|e| {
+        eprintln!("Failed to register signal handlers. Reason - {e}");
+        exit(ExitReason::InternalError);
+    });
+
+    (term, wakeup)
+}
+
+fn main() {
+    preexec::run_if_requested();
+
+    //
+    // Parse command line
+    //
+
+    let mode = get_mode();
+
+    match mode {
+        Mode::Run(config_path, summary) => {
+            let config = get_config(&config_path);
+
+            if let Some(path) = &config.runner_log_file {
+                if let Err(e) = diagnostics::redirect(path) {
+                    eprintln!("{e}");
+                    exit(ExitReason::RedirectionError);
+                }
+            }
+
+            let signals: Vec<i32> = config.handle_signals.iter().map(|signal| signal.0).collect();
+            let (term, wakeup) = register_signal_handlers(&signals);
+
+            let run_result = run_job(&config, &term, &wakeup);
+
+            if summary {
+                result::print_summary(&run_result);
+            }
+
+            if config.print_exit_code {
+                result::print(&run_result, config.output_format);
+            }
+
+            std::process::exit(run_result.runner_exit_code);
+        }
+        Mode::Validate(config_path) => validate_config(&config_path),
+        Mode::SelfTest => {
+            let (term, wakeup) = register_signal_handlers(&signals::SHUTDOWN_SIGNALS);
+
+            match selftest::run(&term, &wakeup) {
+                true => exit(ExitReason::Finished),
+                false => exit(ExitReason::InternalError),
+            }
+        }
+        Mode::Serve(socket_path, max_jobs) => {
+            let (term, wakeup) = register_signal_handlers(&signals::SHUTDOWN_SIGNALS);
+
+            if let Err(e) = server::serve(&socket_path, max_jobs, &term, &wakeup) {
+                eprintln!("Server failed. Reason - {e}");
+                exit(ExitReason::InternalError);
+            }
+
+            exit(ExitReason::Finished);
+        }
+    }
 }
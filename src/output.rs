@@ -0,0 +1,730 @@
+use crate::capture::{Capture, Captured};
+use crate::config::{Config, FileMode, FlushPolicy, SinkSpec, Stream, StreamRedirection, SyslogRedirection};
+use crate::diagnostics;
+use crate::redirection::{file_write, output_file, to_scanned};
+use crate::regex::Regex;
+use crate::reopen::{self, ReopenFile};
+use crate::rotate::RotatingFile;
+
+use subprocess::Popen;
+use syslog::{Facility, Formatter3164, Logger, LoggerBackend, Severity};
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+//
+// Reads child output from pipes and writes it
+// to destinations which can not be passed
+// to the child as a plain file descriptor
+//
+
+const MAX_LINE_LEN: u64 = 64 * 1024;
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+type SharedFile = Arc<Mutex<Box<dyn Write + Send>>>;
+type SharedLogger = Arc<Mutex<Logger<LoggerBackend, Formatter3164>>>;
+type SharedCapture = Arc<Mutex<Capture>>;
+type ByteCounter = Arc<AtomicU64>;
+type SharedMatch = Arc<Mutex<Option<String>>>;
+type SharedTail = Arc<Mutex<Tail>>;
+
+enum Sink {
+    TaggedFile { file: SharedFile, tag: &'static [u8] },
+    Syslog { logger: SharedLogger, severity: Severity },
+    Capture { capture: SharedCapture },
+    // Same data goes to each of them, failed ones do not stop the rest
+    Fanout(Vec<Sink>),
+    Discard,
+}
+
+impl Sink {
+    // Data is either a whole line or a raw chunk
+    fn write(&self, data: &[u8]) -> bool {
+        match self {
+            Sink::TaggedFile { file, tag } => {
+                // Whole line is written under lock to keep lines intact
+                let mut file = file.lock().unwrap();
+                file.write_all(tag).and_then(|_| file.write_all(data)).is_ok()
+            }
+            Sink::Syslog { logger, severity } => {
+                let data = data.strip_suffix(b"\n").unwrap_or(data);
+                let message = String::from_utf8_lossy(data);
+                let mut logger = logger.lock().unwrap();
+                let result = match severity {
+                    Severity::LOG_WARNING => logger.warning(message),
+                    _ => logger.info(message),
+                };
+                result.is_ok()
+            }
+            Sink::Capture { capture } => {
+                capture.lock().unwrap().write(data);
+                true
+            }
+            Sink::Fanout(sinks) => sinks.iter().filter(|sink| sink.write(data)).count() > 0,
+            Sink::Discard => true,
+        }
+    }
+
+    fn flush(&self) -> bool {
+        match self {
+            Sink::TaggedFile { file, .. } => file.lock().unwrap().flush().is_ok(),
+            Sink::Fanout(sinks) => sinks.iter().filter(|sink| sink.flush()).count() > 0,
+            Sink::Syslog { .. } | Sink::Capture { .. } | Sink::Discard => true,
+        }
+    }
+}
+
+//
+// Last lines of a stream, oldest first
+//
+
+struct Tail {
+    limit: usize,
+    lines: VecDeque<String>,
+}
+
+impl Tail {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            lines: VecDeque::with_capacity(limit),
+        }
+    }
+
+    fn push(&mut self, line: &[u8]) {
+        if self.lines.len() == self.limit {
+            self.lines.pop_front();
+        }
+
+        self.lines.push_back(String::from_utf8_lossy(line).into_owned());
+    }
+}
+
+//
+// Looks at output line by line: keeps the first line
+// matching the pattern and the last lines for the tail.
+// Lines are split here, so it works the same
+// whether the sink reads lines or chunks.
+//
+
+struct Scanner {
+    pattern: Option<(Arc<Regex>, SharedMatch)>,
+    tail: Option<SharedTail>,
+    line: Vec<u8>,
+}
+
+impl Scanner {
+    fn feed(&mut self, data: &[u8]) {
+        for part in data.split_inclusive(|&b| b == b'\n') {
+            let room = MAX_LINE_LEN as usize - self.line.len();
+            self.line.extend_from_slice(&part[..part.len().min(room)]);
+
+            if part.ends_with(b"\n") || self.line.len() >= MAX_LINE_LEN as usize {
+                self.check();
+            }
+        }
+    }
+
+    fn check(&mut self) {
+        let line = self.line.strip_suffix(b"\n").unwrap_or(&self.line);
+
+        if let Some(tail) = &self.tail {
+            tail.lock().unwrap().push(line);
+        }
+
+        if let Some((pattern, matched)) = &self.pattern {
+            let mut matched = matched.lock().unwrap();
+            if matched.is_none() && pattern.is_match(line) {
+                *matched = Some(String::from_utf8_lossy(line).into_owned());
+            }
+        }
+
+        self.line.clear();
+    }
+
+    // Last line may have no newline
+    fn finish(&mut self) {
+        if !self.line.is_empty() {
+            self.check();
+        }
+    }
+}
+
+//
+// Tells whether the child writes output: a flag taken
+// by adaptive polling and the time of the last write
+//
+
+struct Activity {
+    written: AtomicBool,
+    base: Instant,
+    // Milliseconds since base, 0 means never
+    last_ms: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            written: AtomicBool::new(false),
+            base: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn mark(&self) {
+        self.written.store(true, Ordering::Relaxed);
+        let elapsed = self.base.elapsed().as_millis().max(1);
+        self.last_ms.store(u64::try_from(elapsed).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    fn since_last(&self) -> Option<Duration> {
+        match self.last_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(self.base.elapsed().saturating_sub(Duration::from_millis(ms))),
+        }
+    }
+}
+
+pub struct StreamOutput {
+    pub bytes: Option<u64>,
+    pub capture: Option<Captured>,
+    pub matched_line: Option<String>,
+    pub tail: Option<Vec<String>>,
+}
+
+pub struct OutputProxy {
+    flush_policy: FlushPolicy,
+    tags: (&'static [u8], &'static [u8]),
+    combined: Option<SharedFile>,
+    stdout_file: Option<SharedFile>,
+    stderr_file: Option<SharedFile>,
+    stdout_capture: Option<SharedCapture>,
+    stderr_capture: Option<SharedCapture>,
+    stderr_pattern: Option<(Arc<Regex>, SharedMatch)>,
+    stdout_tail: Option<SharedTail>,
+    stderr_tail: Option<SharedTail>,
+    syslog: Option<(SharedLogger, SyslogRedirection)>,
+    stdout_sinks: Option<Sink>,
+    stderr_sinks: Option<Sink>,
+    sink_loggers: Vec<SharedLogger>,
+    readers: Vec<JoinHandle<()>>,
+    activity: Arc<Activity>,
+    stdout_bytes: Option<ByteCounter>,
+    stderr_bytes: Option<ByteCounter>,
+}
+
+//
+// Child writes to files directly, so its output
+// size is how much the file has grown during the run
+//
+
+pub struct FileCounter {
+    path: String,
+    initial: u64,
+}
+
+impl FileCounter {
+    pub fn new(stream: &Stream) -> Option<Self> {
+        match stream {
+            Stream::File { path, .. } => Some(Self {
+                path: path.clone(),
+                initial: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self) -> Option<u64> {
+        match fs::metadata(&self.path) {
+            Ok(val) => Some(val.len().saturating_sub(self.initial)),
+            Err(_) => None,
+        }
+    }
+
+    // Modification time of the file stands for the last write
+    pub fn written_within(&self, window: Duration) -> bool {
+        let modified = fs::metadata(&self.path).and_then(|val| val.modified());
+        let since = modified.ok().and_then(|val| SystemTime::now().duration_since(val).ok());
+        since.is_some_and(|val| val <= window) && self.bytes().is_some_and(|val| val > 0)
+    }
+}
+
+fn shared(file: File) -> SharedFile {
+    Arc::new(Mutex::new(Box::new(BufWriter::new(file))))
+}
+
+fn open_shared(path: &str, mode: FileMode, streams: &StreamRedirection) -> Result<SharedFile, String> {
+    if let Some(interval) = streams.rotate_interval_sec {
+        return Ok(Arc::new(Mutex::new(Box::new(RotatingFile::new(path, interval)?))));
+    }
+
+    if streams.reopen_logs_on_sighup {
+        let file = ReopenFile::new(path, mode == FileMode::Append)?;
+        return Ok(Arc::new(Mutex::new(Box::new(file))));
+    }
+
+    match file_write().open(path) {
+        Ok(fd) => Ok(shared(fd)),
+        Err(e) => Err(format!("Failed to open file for write. Path: {path}. Reason - {e}")),
+    }
+}
+
+// Plain output files are proxied only to rotate or reopen them
+fn open_rotating(stream: &Stream, streams: &StreamRedirection) -> Result<Option<SharedFile>, String> {
+    let proxied = streams.rotate_interval_sec.is_some() || streams.reopen_logs_on_sighup;
+    match stream {
+        Stream::File { path, mode } if proxied => Ok(Some(open_shared(path, *mode, streams)?)),
+        _ => Ok(None),
+    }
+}
+
+fn open_syslog(facility: &str, tag: &str) -> Result<SharedLogger, String> {
+    let facility = match Facility::from_str(facility) {
+        Ok(val) => val,
+        Err(_) => return Err(format!("Unknown syslog facility: '{facility}'")),
+    };
+
+    let formatter = Formatter3164 {
+        facility,
+        hostname: None,
+        process: tag.to_string(),
+        pid: std::process::id(),
+    };
+
+    match syslog::unix(formatter) {
+        Ok(val) => Ok(Arc::new(Mutex::new(val))),
+        Err(e) => Err(format!("Failed to connect to syslog. Reason - {e}")),
+    }
+}
+
+fn read_lines(
+    source: File,
+    sink: &Sink,
+    activity: &Activity,
+    bytes: &AtomicU64,
+    flush_policy: FlushPolicy,
+    scanner: &mut Option<Scanner>,
+) {
+    let mut reader = BufReader::new(source);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        // Overlong lines are split to keep memory bounded
+        match reader.by_ref().take(MAX_LINE_LEN).read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+
+        activity.mark();
+        bytes.fetch_add(line.len() as u64, Ordering::Relaxed);
+
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+
+        if let Some(scanner) = scanner {
+            scanner.feed(&line);
+        }
+
+        if !sink.write(&line) {
+            break;
+        }
+
+        if flush_policy == FlushPolicy::Line && !sink.flush() {
+            break;
+        }
+    }
+}
+
+fn read_chunks(
+    mut source: File,
+    sink: &Sink,
+    activity: &Activity,
+    bytes: &AtomicU64,
+    scanner: &mut Option<Scanner>,
+) {
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        let n = match source.read(&mut chunk) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+
+        activity.mark();
+        bytes.fetch_add(n as u64, Ordering::Relaxed);
+
+        if let Some(scanner) = scanner {
+            scanner.feed(&chunk[..n]);
+        }
+
+        if !sink.write(&chunk[..n]) || !sink.flush() {
+            break;
+        }
+    }
+}
+
+fn spawn_reader(
+    source: File,
+    sink: Sink,
+    activity: Arc<Activity>,
+    bytes: ByteCounter,
+    flush_policy: FlushPolicy,
+    mut scanner: Option<Scanner>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        match (&sink, flush_policy) {
+            // Captured output is kept byte for byte
+            (Sink::Discard | Sink::Capture { .. }, _) | (_, FlushPolicy::Immediate) => {
+                read_chunks(source, &sink, &activity, &bytes, &mut scanner)
+            }
+            _ => read_lines(source, &sink, &activity, &bytes, flush_policy, &mut scanner),
+        }
+
+        if let Some(scanner) = &mut scanner {
+            scanner.finish();
+        }
+
+        sink.flush();
+    })
+}
+
+fn open_sinks(
+    specs: &[SinkSpec],
+    stderr: bool,
+    streams: &StreamRedirection,
+    files: &mut HashMap<String, SharedFile>,
+    loggers: &mut Vec<SharedLogger>,
+) -> Result<Option<Sink>, String> {
+    if specs.is_empty() {
+        return Ok(None);
+    }
+
+    let (stdout_tag, stderr_tag) = streams.tag_style.tags();
+    let (line_tag, severity, inherited_fd) = match stderr {
+        false => (stdout_tag, Severity::LOG_INFO, libc::STDOUT_FILENO),
+        true => (stderr_tag, Severity::LOG_WARNING, diagnostics::child_stderr()),
+    };
+
+    let mut sinks = Vec::new();
+
+    for spec in specs {
+        let sink = match spec {
+            SinkSpec::File { path, mode, tag } => {
+                let file = match files.get(path) {
+                    Some(file) => Arc::clone(file),
+                    None => {
+                        let file = open_shared(path, *mode, streams)?;
+                        files.insert(path.clone(), Arc::clone(&file));
+                        file
+                    }
+                };
+
+                let tag = if *tag { line_tag } else { b"" };
+                Sink::TaggedFile { file, tag }
+            }
+            SinkSpec::Syslog { facility, tag } => {
+                let logger = open_syslog(facility, tag)?;
+                loggers.push(Arc::clone(&logger));
+                Sink::Syslog { logger, severity }
+            }
+            SinkSpec::Inherit => match output_file(&Stream::Inherit, inherited_fd)? {
+                Some(file) => Sink::TaggedFile { file: shared(file), tag: b"" },
+                None => continue,
+            },
+        };
+
+        sinks.push(sink);
+    }
+
+    Ok(Some(Sink::Fanout(sinks)))
+}
+
+impl OutputProxy {
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let streams = &config.streams;
+
+        if streams.reopen_logs_on_sighup {
+            reopen::register()?;
+        }
+
+        let combined = match &streams.combined {
+            Some(path) => Some(open_shared(path, FileMode::Truncate, streams)?),
+            None => None,
+        };
+
+        // Terminal output of the child is always read by the runner
+        let stdout_file = match open_rotating(&streams.stdout, streams)? {
+            None if config.use_pty || to_scanned(config, false) => {
+                output_file(&streams.stdout, libc::STDOUT_FILENO)?.map(shared)
+            }
+            file => file,
+        };
+
+        let stderr_file = match (&stdout_file, streams.stdout.path()) {
+            // Both streams share the same bucket file
+            (Some(file), Some(path)) if streams.stderr.path() == Some(path) => Some(Arc::clone(file)),
+            _ => match open_rotating(&streams.stderr, streams)? {
+                None if to_scanned(config, true) => output_file(&streams.stderr, diagnostics::child_stderr())?.map(shared),
+                file => file,
+            },
+        };
+
+        let stderr_pattern = match &config.fail_on_stderr_pattern {
+            Some(pattern) => Some((Arc::new(Regex::new(pattern)?), SharedMatch::default())),
+            None => None,
+        };
+
+        let tail = || config.result_tail_lines.map(|limit| Arc::new(Mutex::new(Tail::new(limit))));
+
+        let capture = |stream: &Stream, name| match stream {
+            Stream::Capture => Some(Arc::new(Mutex::new(Capture::new(name, streams.capture_spill_bytes)))),
+            _ => None,
+        };
+
+        let stdout_capture = capture(&streams.stdout, "stdout");
+        let stderr_capture = capture(&streams.stderr, "stderr");
+
+        let syslog = match &config.streams.syslog {
+            Some(val) => Some((open_syslog(&val.facility, &val.tag)?, val.clone())),
+            None => None,
+        };
+
+        // Both streams write the same file through one handle
+        let mut files = HashMap::new();
+        let mut sink_loggers = Vec::new();
+        let stdout_sinks = open_sinks(&streams.sinks.stdout, false, streams, &mut files, &mut sink_loggers)?;
+        let stderr_sinks = open_sinks(&streams.sinks.stderr, true, streams, &mut files, &mut sink_loggers)?;
+
+        Ok(Self {
+            flush_policy: config.streams.flush_policy,
+            tags: streams.tag_style.tags(),
+            combined,
+            stdout_file,
+            stderr_file,
+            stdout_capture,
+            stderr_capture,
+            stderr_pattern,
+            stdout_tail: tail(),
+            stderr_tail: tail(),
+            syslog,
+            stdout_sinks,
+            stderr_sinks,
+            sink_loggers,
+            readers: Vec::new(),
+            activity: Arc::new(Activity::new()),
+            stdout_bytes: None,
+            stderr_bytes: None,
+        })
+    }
+
+    fn attach(&mut self, source: Option<File>, sink: Sink, bytes: &Option<ByteCounter>, scanner: Option<Scanner>) {
+        if let (Some(source), Some(bytes)) = (source, bytes) {
+            let activity = Arc::clone(&self.activity);
+            let bytes = Arc::clone(bytes);
+            self.readers.push(spawn_reader(source, sink, activity, bytes, self.flush_policy, scanner));
+        }
+    }
+
+    fn scanner(&self, stderr: bool) -> Option<Scanner> {
+        let (pattern, tail) = match stderr {
+            false => (None, &self.stdout_tail),
+            true => (self.stderr_pattern.as_ref(), &self.stderr_tail),
+        };
+
+        if pattern.is_none() && tail.is_none() {
+            return None;
+        }
+
+        Some(Scanner {
+            pattern: pattern.map(|(pattern, matched)| (Arc::clone(pattern), Arc::clone(matched))),
+            tail: tail.clone(),
+            line: Vec::new(),
+        })
+    }
+
+    pub fn start(&mut self, ps: &mut Popen) {
+        // Every pipe gets a reader below, so every pipe is counted
+        self.stdout_bytes = ps.stdout.is_some().then(ByteCounter::default);
+        self.stderr_bytes = ps.stderr.is_some().then(ByteCounter::default);
+        let stdout_bytes = self.stdout_bytes.clone();
+        let stderr_bytes = self.stderr_bytes.clone();
+
+        if let Some(pid) = ps.pid() {
+            for logger in &self.sink_loggers {
+                logger.lock().unwrap().formatter.pid = pid;
+            }
+        }
+
+        if let Some(sink) = self.stdout_sinks.take() {
+            self.attach(ps.stdout.take(), sink, &stdout_bytes, self.scanner(false));
+        }
+
+        if let Some(sink) = self.stderr_sinks.take() {
+            self.attach(ps.stderr.take(), sink, &stderr_bytes, self.scanner(true));
+        }
+
+        if let Some(file) = self.combined.clone() {
+            let (stdout_tag, stderr_tag) = self.tags;
+            self.attach(ps.stdout.take(), Sink::TaggedFile { file, tag: stdout_tag }, &stdout_bytes, self.scanner(false));
+
+            let file = self.combined.clone().unwrap();
+            self.attach(ps.stderr.take(), Sink::TaggedFile { file, tag: stderr_tag }, &stderr_bytes, self.scanner(true));
+        }
+
+        if let Some((logger, syslog)) = self.syslog.clone() {
+            if let Some(pid) = ps.pid() {
+                logger.lock().unwrap().formatter.pid = pid;
+            }
+
+            if syslog.stdout {
+                let logger = Arc::clone(&logger);
+                let severity = Severity::LOG_INFO;
+                self.attach(ps.stdout.take(), Sink::Syslog { logger, severity }, &stdout_bytes, self.scanner(false));
+            }
+
+            if syslog.stderr {
+                let severity = Severity::LOG_WARNING;
+                self.attach(ps.stderr.take(), Sink::Syslog { logger, severity }, &stderr_bytes, self.scanner(true));
+            }
+        }
+
+        if let Some(capture) = self.stdout_capture.clone() {
+            self.attach(ps.stdout.take(), Sink::Capture { capture }, &stdout_bytes, self.scanner(false));
+        }
+
+        if let Some(capture) = self.stderr_capture.clone() {
+            self.attach(ps.stderr.take(), Sink::Capture { capture }, &stderr_bytes, self.scanner(true));
+        }
+
+        if let Some(file) = self.stdout_file.clone() {
+            self.attach(ps.stdout.take(), Sink::TaggedFile { file, tag: b"" }, &stdout_bytes, self.scanner(false));
+        }
+
+        if let Some(file) = self.stderr_file.clone() {
+            self.attach(ps.stderr.take(), Sink::TaggedFile { file, tag: b"" }, &stderr_bytes, self.scanner(true));
+        }
+
+        //
+        // Pipes nobody reads from are drained so that
+        // the child never blocks on a full pipe.
+        // Stdin pipe is closed, child gets EOF.
+        //
+
+        self.attach(ps.stdout.take(), Sink::Discard, &stdout_bytes, self.scanner(false));
+        self.attach(ps.stderr.take(), Sink::Discard, &stderr_bytes, self.scanner(true));
+        ps.stdin.take();
+    }
+
+    // Whether child has written anything since last call
+    pub fn take_activity(&self) -> bool {
+        self.activity.written.swap(false, Ordering::Relaxed)
+    }
+
+    // Whether child has written anything within the window
+    pub fn active_within(&self, window: Duration) -> bool {
+        self.activity.since_last().is_some_and(|val| val <= window)
+    }
+
+    //
+    // Waits for the readers to see EOF. A grandchild holding
+    // the pipes open keeps them busy after the child is gone,
+    // so with a timeout they are left behind. Returns false
+    // in that case, output read so far is still reported.
+    //
+
+    pub fn drain(&mut self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.and_then(|val| Instant::now().checked_add(val));
+
+        if let Some(deadline) = deadline {
+            while self.readers.iter().any(|reader| !reader.is_finished()) {
+                if Instant::now() >= deadline {
+                    self.readers.clear();
+                    return false;
+                }
+
+                thread::sleep(DRAIN_POLL_INTERVAL);
+            }
+        }
+
+        for reader in self.readers.drain(..) {
+            let _ = reader.join();
+        }
+
+        true
+    }
+
+    // Returns what has been read from stdout and stderr pipes
+    pub fn finish(mut self) -> (StreamOutput, StreamOutput) {
+        self.drain(None);
+
+        let output = |bytes: Option<ByteCounter>,
+                      capture: Option<SharedCapture>,
+                      matched: Option<SharedMatch>,
+                      tail: Option<SharedTail>| StreamOutput {
+            bytes: bytes.map(|val| val.load(Ordering::Relaxed)),
+            capture: capture.map(|val| val.lock().unwrap().finish()),
+            matched_line: matched.and_then(|val| val.lock().unwrap().take()),
+            tail: tail.map(|val| val.lock().unwrap().lines.drain(..).collect()),
+        };
+
+        let stderr_match = self.stderr_pattern.map(|(_, matched)| matched);
+        (
+            output(self.stdout_bytes, self.stdout_capture, None, self.stdout_tail),
+            output(self.stderr_bytes, self.stderr_capture, stderr_match, self.stderr_tail),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scanner(pattern: &str, tail: usize) -> (Scanner, SharedMatch, SharedTail) {
+        let matched = Arc::new(Mutex::new(None));
+        let lines = Arc::new(Mutex::new(Tail::new(tail)));
+        let scanner = Scanner {
+            pattern: Some((Arc::new(Regex::new(pattern).unwrap()), Arc::clone(&matched))),
+            tail: Some(Arc::clone(&lines)),
+            line: Vec::new(),
+        };
+
+        (scanner, matched, lines)
+    }
+
+    #[test]
+    fn match_split_across_chunks() {
+        let (mut scanner, matched, _) = scanner("^FATAL: .*corrupt", 1);
+        for chunk in ["ok\nFA", "TAL: heap cor", "rupt", "ed\nnext\n"] {
+            scanner.feed(chunk.as_bytes());
+        }
+
+        assert_eq!(matched.lock().unwrap().as_deref(), Some("FATAL: heap corrupted"));
+    }
+
+    #[test]
+    fn first_match_is_kept() {
+        let (mut scanner, matched, _) = scanner("error", 1);
+        scanner.feed(b"error one\nerror two\n");
+        assert_eq!(matched.lock().unwrap().as_deref(), Some("error one"));
+    }
+
+    #[test]
+    fn last_line_without_newline_is_checked_on_finish() {
+        let (mut scanner, matched, tail) = scanner("^bye$", 2);
+        scanner.feed(b"one\ntwo\nby");
+        scanner.feed(b"e");
+        assert!(matched.lock().unwrap().is_none());
+
+        scanner.finish();
+        assert_eq!(matched.lock().unwrap().as_deref(), Some("bye"));
+        assert_eq!(tail.lock().unwrap().lines, ["two", "bye"]);
+    }
+}
@@ -0,0 +1,658 @@
+use crate::config::{Config, IoniceClass, Limits, NETNS_DIR, PRIVATE_TMP_DIR};
+
+use serde::{Deserialize, Serialize};
+use subprocess::{PopenConfig, Redirection};
+
+use std::env;
+use std::ffi::{CStr, CString, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+//
+// Subprocess crate has no pre-exec callback, so the
+// runner spawns itself with this variable set instead.
+// The copy sees the variable, applies the requested
+// setup to its own process and execs the target.
+//
+
+const PREEXEC_VAR: &str = "SBXBIN_PREEXEC";
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PreExec {
+    pub argv: Vec<OsString>,
+    // Program name shown to the target instead of argv[0]
+    #[serde(default)]
+    pub argv0: Option<OsString>,
+    pub cpu_affinity: Option<Vec<usize>>,
+    pub limits: Limits,
+    #[serde(default)]
+    pub nice: Option<i32>,
+    // Packed class and level, see ioprio_value
+    #[serde(default)]
+    pub ioprio: Option<i32>,
+    #[serde(default)]
+    pub new_session: bool,
+    #[serde(default)]
+    pub netns: Option<String>,
+    #[serde(default)]
+    pub private_tmp: bool,
+    #[serde(default)]
+    pub private_tmp_size_mb: Option<u64>,
+    #[serde(default)]
+    pub controlling_tty: bool,
+    #[serde(default)]
+    pub parent_pid: Option<u32>,
+    #[serde(default)]
+    pub drop_capabilities: bool,
+    #[serde(default)]
+    pub stop: bool,
+    // Write end of the report pipe, see Report
+    #[serde(default)]
+    pub report_fd: Option<RawFd>,
+}
+
+impl PreExec {
+    pub fn from_config(config: &Config, argv: Vec<OsString>) -> Self {
+        Self {
+            argv,
+            argv0: config.argv0.clone().map(OsString::from),
+            cpu_affinity: config.cpu_affinity.clone(),
+            limits: config.limits.clone(),
+            nice: config.nice,
+            ioprio: ioprio_value(config.ionice_class, config.ionice_level),
+            new_session: config.new_session,
+            netns: config.netns.clone(),
+            private_tmp: config.private_tmp,
+            private_tmp_size_mb: config.private_tmp_size_mb,
+            controlling_tty: config.use_pty,
+            parent_pid: config.kill_on_runner_death.then(std::process::id),
+            drop_capabilities: config.drop_capabilities,
+            stop: config.start_stopped,
+            report_fd: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cpu_affinity.is_none()
+            && self.limits.is_empty()
+            && self.nice.is_none()
+            && self.ioprio.is_none()
+            && !self.new_session
+            && self.netns.is_none()
+            && !self.private_tmp
+            && !self.controlling_tty
+            && self.parent_pid.is_none()
+            && !self.drop_capabilities
+            && !self.stop
+    }
+}
+
+//
+// Level alone implies best effort class, the one
+// every process has by default. Class alone gets
+// the middle level, as ionice(1) does.
+//
+
+fn ioprio_value(class: Option<IoniceClass>, level: Option<u8>) -> Option<i32> {
+    const IOPRIO_CLASS_SHIFT: i32 = 13;
+
+    let class = match (class, level) {
+        (None, None) => return None,
+        (Some(class), _) => class,
+        (None, Some(_)) => IoniceClass::BestEffort,
+    };
+
+    let level = match class {
+        IoniceClass::Idle => 0,
+        _ => level.unwrap_or(4),
+    };
+
+    Some((class as i32) << IOPRIO_CLASS_SHIFT | level as i32)
+}
+
+//
+// Failed pre-exec step: syscall name and
+// errno it has returned. Sent back to the
+// runner as a single json object.
+//
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StepError {
+    pub step: String,
+    pub errno: i32,
+}
+
+impl StepError {
+    fn new(step: &str, errno: i32) -> Self {
+        Self {
+            step: String::from(step),
+            errno,
+        }
+    }
+
+    fn last(step: &str) -> Self {
+        let errno = std::io::Error::last_os_error().raw_os_error();
+        Self::new(step, errno.unwrap_or(0))
+    }
+}
+
+fn errno_name(errno: i32) -> Option<&'static str> {
+    let name = match errno {
+        libc::EPERM => "EPERM",
+        libc::ENOENT => "ENOENT",
+        libc::ESRCH => "ESRCH",
+        libc::EINTR => "EINTR",
+        libc::EIO => "EIO",
+        libc::E2BIG => "E2BIG",
+        libc::ENOEXEC => "ENOEXEC",
+        libc::EBADF => "EBADF",
+        libc::EAGAIN => "EAGAIN",
+        libc::ENOMEM => "ENOMEM",
+        libc::EACCES => "EACCES",
+        libc::EFAULT => "EFAULT",
+        libc::EBUSY => "EBUSY",
+        libc::ENOTDIR => "ENOTDIR",
+        libc::EISDIR => "EISDIR",
+        libc::EINVAL => "EINVAL",
+        libc::EMFILE => "EMFILE",
+        libc::ENOTTY => "ENOTTY",
+        libc::ETXTBSY => "ETXTBSY",
+        libc::ENAMETOOLONG => "ENAMETOOLONG",
+        libc::ENOSYS => "ENOSYS",
+        libc::ELOOP => "ELOOP",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = unsafe { CStr::from_ptr(libc::strerror(self.errno)) }.to_string_lossy();
+        match errno_name(self.errno) {
+            Some(name) => write!(f, "failed to {}: {name} ({reason})", self.step),
+            None => write!(f, "failed to {}: {reason}", self.step),
+        }
+    }
+}
+
+//
+// Pipe from the spawned copy back to the runner. Write
+// end is inherited by the copy and closed on exec of the
+// target, so the runner reads either a StepError or EOF.
+//
+
+pub struct Report {
+    reader: File,
+    writer: Option<File>,
+}
+
+impl Report {
+    fn open() -> Result<Self, String> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(format!("Failed to create report pipe. Reason - {e}"));
+        }
+
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+        let writer = unsafe { File::from_raw_fd(fds[1]) };
+
+        if unsafe { libc::fcntl(writer.as_raw_fd(), libc::F_SETFD, 0) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(format!("Failed to setup report pipe. Reason - {e}"));
+        }
+
+        Ok(Self {
+            reader,
+            writer: Some(writer),
+        })
+    }
+
+    //
+    // Must be called right after spawn. Blocks until
+    // the target is exec'ed or the spawned copy exits.
+    //
+
+    pub fn read(mut self) -> Option<StepError> {
+        drop(self.writer.take());
+
+        let mut data = String::new();
+        if self.reader.read_to_string(&mut data).is_err() || data.is_empty() {
+            return None;
+        }
+
+        serde_json::from_str(&data).ok()
+    }
+}
+
+//
+// Returns argv to pass into Popen::create.
+// If no pre-exec steps are requested
+// the target is started directly.
+//
+
+pub fn wrap(mut spec: PreExec, pconf: &mut PopenConfig) -> Result<(Vec<OsString>, Option<Report>), String> {
+    if spec.is_empty() {
+        let mut argv = spec.argv;
+        if let Some(argv0) = spec.argv0 {
+            pconf.executable = Some(mem::replace(&mut argv[0], argv0));
+        }
+
+        return Ok((argv, None));
+    }
+
+    let report = Report::open()?;
+    spec.report_fd = report.writer.as_ref().map(|file| file.as_raw_fd());
+
+    let exe = match env::current_exe() {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to locate runner executable. Reason - {e}")),
+    };
+
+    let json = match serde_json::to_string(&spec) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to serialize pre-exec steps. Reason - {e}")),
+    };
+
+    let envs = pconf.env.get_or_insert_with(PopenConfig::current_env);
+    envs.push((OsString::from(PREEXEC_VAR), OsString::from(json)));
+
+    Ok((vec![exe.into_os_string()], Some(report)))
+}
+
+fn set_session() -> Result<(), StepError> {
+    if unsafe { libc::setsid() } < 0 {
+        return Err(StepError::last("setsid"));
+    }
+
+    Ok(())
+}
+
+//
+// Joins a namespace created by ip netns add, which
+// bind mounts it under NETNS_DIR. Needs CAP_SYS_ADMIN.
+//
+
+fn enter_netns(name: &str) -> Result<(), StepError> {
+    let path = Path::new(NETNS_DIR).join(name);
+    let file = match File::open(&path) {
+        Ok(val) => val,
+        Err(e) => return Err(StepError::new(&format!("open netns '{name}'"), e.raw_os_error().unwrap_or(0))),
+    };
+
+    if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+        return Err(StepError::last(&format!("setns(CLONE_NEWNET) into '{name}'")));
+    }
+
+    Ok(())
+}
+
+//
+// Mounts a fresh tmpfs over /tmp in a new mount namespace,
+// so temp files neither leak in from other runs nor out.
+// Propagation is made private first, otherwise the mount
+// would show up on the host. Needs CAP_SYS_ADMIN.
+//
+
+fn mount_private_tmp(size_mb: Option<u64>) -> Result<(), StepError> {
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        return Err(StepError::last("unshare(CLONE_NEWNS)"));
+    }
+
+    let root = CString::new("/").unwrap();
+    let flags = libc::MS_REC | libc::MS_PRIVATE;
+    let null = std::ptr::null();
+    if unsafe { libc::mount(null, root.as_ptr(), null, flags, null.cast()) } != 0 {
+        return Err(StepError::last("mount(MS_PRIVATE) of /"));
+    }
+
+    let target = CString::new(PRIVATE_TMP_DIR).unwrap();
+    let tmpfs = CString::new("tmpfs").unwrap();
+    let options = match size_mb {
+        Some(size) => CString::new(format!("mode=1777,size={size}m")).unwrap(),
+        None => CString::new("mode=1777").unwrap(),
+    };
+    let flags = libc::MS_NOSUID | libc::MS_NODEV;
+    if unsafe { libc::mount(tmpfs.as_ptr(), target.as_ptr(), tmpfs.as_ptr(), flags, options.as_ptr().cast()) } != 0 {
+        return Err(StepError::last(&format!("mount(tmpfs) at {PRIVATE_TMP_DIR}")));
+    }
+
+    Ok(())
+}
+
+// Stdout is the pty slave, only a session leader may acquire it
+fn set_controlling_tty() -> Result<(), StepError> {
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCSCTTY, 0) } != 0 {
+        return Err(StepError::last("ioctl(TIOCSCTTY)"));
+    }
+
+    Ok(())
+}
+
+//
+// Death signal is tied to the thread that has forked
+// the child, not to the whole runner process. Runner
+// spawns from the main thread, so this holds here.
+//
+
+fn set_parent_death_signal(parent_pid: u32) -> Result<(), StepError> {
+    if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) } != 0 {
+        return Err(StepError::last("prctl(PR_SET_PDEATHSIG)"));
+    }
+
+    // Runner may have died before prctl call
+    if unsafe { libc::getppid() } as u32 != parent_pid {
+        return Err(StepError::new("find runner process", libc::ESRCH));
+    }
+
+    Ok(())
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+//
+// Clears ambient, bounding, effective, permitted and
+// inheritable sets, then forbids gaining privileges
+// through setuid or file capabilities on exec.
+// Dropping the bounding set needs CAP_SETPCAP.
+//
+
+fn drop_capabilities() -> Result<(), StepError> {
+    let clear_ambient = libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong;
+    if unsafe { libc::prctl(libc::PR_CAP_AMBIENT, clear_ambient, 0, 0, 0) } != 0 {
+        return Err(StepError::last("prctl(PR_CAP_AMBIENT_CLEAR_ALL)"));
+    }
+
+    // Kernel rejects capabilities it does not know with EINVAL
+    for cap in 0..64 as libc::c_ulong {
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } != 0 {
+            if std::io::Error::last_os_error().raw_os_error() == Some(libc::EINVAL) {
+                break;
+            }
+
+            return Err(StepError::last("prctl(PR_CAPBSET_DROP)"));
+        }
+    }
+
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+
+    let data = [CapData::default(), CapData::default()];
+    if unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) } != 0 {
+        return Err(StepError::last("capset"));
+    }
+
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(StepError::last("prctl(PR_SET_NO_NEW_PRIVS)"));
+    }
+
+    Ok(())
+}
+
+// Stays stopped until SIGCONT, the target has not run yet
+fn stop_self() -> Result<(), StepError> {
+    if unsafe { libc::raise(libc::SIGSTOP) } != 0 {
+        return Err(StepError::last("raise(SIGSTOP)"));
+    }
+
+    Ok(())
+}
+
+// Absolute value, lowering it below the current one needs CAP_SYS_NICE
+fn set_nice(nice: i32) -> Result<(), StepError> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+        return Err(StepError::last("setpriority"));
+    }
+
+    Ok(())
+}
+
+// Linux only, there is no libc wrapper
+fn set_ioprio(ioprio: i32) -> Result<(), StepError> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+        return Err(StepError::last("ioprio_set"));
+    }
+
+    Ok(())
+}
+
+fn set_cpu_affinity(cores: &[usize]) -> Result<(), StepError> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+
+        for &core in cores {
+            libc::CPU_SET(core, &mut set);
+        }
+
+        let size = mem::size_of::<libc::cpu_set_t>();
+        if libc::sched_setaffinity(0, size, &set) != 0 {
+            return Err(StepError::last("sched_setaffinity"));
+        }
+    }
+
+    Ok(())
+}
+
+fn set_rlimit(resource: libc::__rlimit_resource_t, name: &str, value: u64) -> Result<(), StepError> {
+    let limit = libc::rlimit {
+        rlim_cur: value,
+        rlim_max: value,
+    };
+
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(StepError::last(&format!("setrlimit({name})")));
+    }
+
+    Ok(())
+}
+
+fn apply_limits(limits: &Limits) -> Result<(), StepError> {
+    let rlimits = [
+        (libc::RLIMIT_CPU, "RLIMIT_CPU", limits.cpu_time_sec),
+        (libc::RLIMIT_AS, "RLIMIT_AS", limits.memory_bytes),
+        (libc::RLIMIT_NOFILE, "RLIMIT_NOFILE", limits.max_open_files),
+        (libc::RLIMIT_FSIZE, "RLIMIT_FSIZE", limits.output_bytes),
+        (libc::RLIMIT_NPROC, "RLIMIT_NPROC", limits.max_threads),
+    ];
+
+    for (resource, name, value) in rlimits {
+        if let Some(value) = value {
+            set_rlimit(resource, name, value)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn apply(spec: &PreExec) -> Result<(), StepError> {
+    if spec.new_session || spec.controlling_tty {
+        set_session()?;
+    }
+
+    if spec.controlling_tty {
+        set_controlling_tty()?;
+    }
+
+    if let Some(pid) = spec.parent_pid {
+        set_parent_death_signal(pid)?;
+    }
+
+    if let Some(name) = &spec.netns {
+        enter_netns(name)?;
+    }
+
+    if spec.private_tmp {
+        mount_private_tmp(spec.private_tmp_size_mb)?;
+    }
+
+    if let Some(cores) = &spec.cpu_affinity {
+        set_cpu_affinity(cores)?;
+    }
+
+    apply_limits(&spec.limits)?;
+
+    if let Some(nice) = spec.nice {
+        set_nice(nice)?;
+    }
+
+    if let Some(ioprio) = spec.ioprio {
+        set_ioprio(ioprio)?;
+    }
+
+    // Last, as the steps above may need privileges
+    if spec.drop_capabilities {
+        drop_capabilities()?;
+    }
+
+    if spec.stop {
+        stop_self()?;
+    }
+
+    Ok(())
+}
+
+//
+// Entry point of the spawned copy. Does
+// nothing unless the variable is set,
+// otherwise never returns.
+//
+
+pub fn run_if_requested() {
+    let json = match env::var(PREEXEC_VAR) {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+
+    env::remove_var(PREEXEC_VAR);
+
+    let spec: PreExec = match serde_json::from_str(&json) {
+        Ok(val) => val,
+        Err(e) => {
+            eprintln!("Failed to parse pre-exec steps. Reason - {e}");
+            std::process::exit(127);
+        }
+    };
+
+    // Report pipe must not leak into the target
+    if let Some(fd) = spec.report_fd {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+
+    if let Err(e) = apply(&spec) {
+        fail(&spec, e);
+    }
+
+    let e = command(&spec).exec();
+    let step = format!("exec '{}'", spec.argv[0].to_string_lossy());
+    fail(&spec, StepError::new(&step, e.raw_os_error().unwrap_or(0)));
+}
+
+// Runner logs the reported error, print it only if reporting fails
+fn fail(spec: &PreExec, e: StepError) -> ! {
+    let reported = match (spec.report_fd, serde_json::to_string(&e)) {
+        (Some(fd), Ok(json)) => {
+            let mut file = unsafe { File::from_raw_fd(fd) };
+            file.write_all(json.as_bytes()).is_ok()
+        }
+        _ => false,
+    };
+
+    if !reported {
+        eprintln!("Pre-exec {e}");
+    }
+
+    std::process::exit(127);
+}
+
+fn command(spec: &PreExec) -> Command {
+    let mut command = Command::new(&spec.argv[0]);
+    command.args(&spec.argv[1..]);
+
+    if let Some(argv0) = &spec.argv0 {
+        command.arg0(argv0);
+    }
+
+    command
+}
+
+fn to_stdio(redirection: Redirection, stdout: Option<&std::fs::File>) -> Result<Stdio, String> {
+    let stdio = match redirection {
+        Redirection::None => Stdio::inherit(),
+        Redirection::File(file) => Stdio::from(file),
+        Redirection::Merge => match stdout.map(|file| file.try_clone()) {
+            Some(Ok(file)) => Stdio::from(file),
+            _ => return Err(String::from("Failed to merge stderr into stdout")),
+        },
+        _ => return Err(String::from("Pipes are not supported in exec mode")),
+    };
+
+    Ok(stdio)
+}
+
+//
+// Passthrough mode: the runner replaces itself
+// with the target, so there is nothing left to
+// monitor it. Returns only if exec has failed.
+//
+
+pub fn exec(spec: PreExec, pconf: PopenConfig) -> String {
+    if let Err(e) = apply(&spec) {
+        return format!("Pre-exec {e}");
+    }
+
+    let stdout_file = match &pconf.stdout {
+        Redirection::File(file) => Some(file),
+        _ => None,
+    };
+
+    let stdout_copy = stdout_file.and_then(|file| file.try_clone().ok());
+
+    let stdin = to_stdio(pconf.stdin, None);
+    let stderr = to_stdio(pconf.stderr, stdout_copy.as_ref());
+    let stdout = to_stdio(pconf.stdout, None);
+
+    let (stdin, stdout, stderr) = match (stdin, stdout, stderr) {
+        (Ok(i), Ok(o), Ok(e)) => (i, o, e),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return e,
+    };
+
+    let mut command = command(&spec);
+    command.stdin(stdin).stdout(stdout).stderr(stderr);
+
+    if let Some(envs) = pconf.env {
+        command.env_clear().envs(envs);
+    }
+
+    if let Some(cwd) = pconf.cwd {
+        command.current_dir(cwd);
+    }
+
+    let e = command.exec();
+    format!("Failed to exec '{}'. Reason - {e}", spec.argv[0].to_string_lossy())
+}
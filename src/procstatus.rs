@@ -0,0 +1,168 @@
+use serde::Serialize;
+
+use std::fs;
+
+//
+// Best-effort memory figures from /proc/<pid>/status.
+// Values are in kB as the kernel reports them.
+//
+
+#[derive(Serialize, Default, Clone, Copy)]
+pub struct ProcStatus {
+    pub vm_peak_kb: u64,
+    pub vm_size_kb: u64,
+    pub vm_hwm_kb: u64,
+    pub vm_rss_kb: u64,
+    pub vm_swap_kb: u64,
+}
+
+// Numeric "Name: value kB" lines, others are skipped
+fn fields(content: &str) -> impl Iterator<Item = (&str, u64)> {
+    content.lines().filter_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        let value = value.trim().trim_end_matches("kB").trim();
+        Some((name, value.parse::<u64>().ok()?))
+    })
+}
+
+fn parse(content: &str) -> Option<ProcStatus> {
+    let mut status = ProcStatus::default();
+    let mut has_peak = false;
+
+    for (name, value) in fields(content) {
+        match name {
+            "VmPeak" => {
+                status.vm_peak_kb = value;
+                has_peak = true;
+            }
+            "VmSize" => status.vm_size_kb = value,
+            "VmHWM" => status.vm_hwm_kb = value,
+            "VmRSS" => status.vm_rss_kb = value,
+            "VmSwap" => status.vm_swap_kb = value,
+            _ => {}
+        }
+    }
+
+    // Zombies and kernel threads have no memory lines
+    has_peak.then_some(status)
+}
+
+// Number of threads of the process, main one included
+pub fn threads(pid: u32) -> Option<u64> {
+    let content = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let threads = fields(&content).find(|(name, _)| *name == "Threads");
+    threads.map(|(_, value)| value)
+}
+
+// Number of open descriptors of the process
+pub fn open_files(pid: u32) -> Option<u64> {
+    let entries = fs::read_dir(format!("/proc/{pid}/fd")).ok()?;
+    Some(entries.count() as u64)
+}
+
+//
+// Keeps the snapshot with the highest VmPeak.
+// Missing or unreadable status file is skipped.
+//
+
+pub struct PeakTracker {
+    path: String,
+    peak: Option<ProcStatus>,
+}
+
+impl PeakTracker {
+    pub fn new(pid: u32) -> Self {
+        Self {
+            path: format!("/proc/{pid}/status"),
+            peak: None,
+        }
+    }
+
+    pub fn sample(&mut self) {
+        let status = match fs::read_to_string(&self.path).ok().as_deref().and_then(parse) {
+            Some(val) => val,
+            None => return,
+        };
+
+        if self.peak.is_none_or(|peak| status.vm_peak_kb > peak.vm_peak_kb) {
+            self.peak = Some(status);
+        }
+    }
+
+    pub fn peak(&self) -> Option<ProcStatus> {
+        self.peak
+    }
+}
+
+//
+// Memory of the whole process from /proc/<pid>/smaps_rollup,
+// in kB. Pss splits shared pages between the processes
+// mapping them, so shared libraries are not counted in full.
+//
+
+#[derive(Serialize, Default, Clone, Copy)]
+pub struct SmapsRollup {
+    pub rss_kb: u64,
+    pub pss_kb: u64,
+    pub pss_anon_kb: u64,
+    pub pss_file_kb: u64,
+    pub shared_clean_kb: u64,
+    pub shared_dirty_kb: u64,
+    pub private_clean_kb: u64,
+    pub private_dirty_kb: u64,
+    pub swap_kb: u64,
+}
+
+//
+// Reading it walks the page tables of the child, which
+// takes a while for large ones. Memory is released on
+// exit before the child can be reaped, so the last
+// successful read is the closest to the exit.
+//
+
+pub fn smaps_rollup(pid: u32) -> Option<SmapsRollup> {
+    let content = fs::read_to_string(format!("/proc/{pid}/smaps_rollup")).ok()?;
+    let mut rollup = SmapsRollup::default();
+    let mut has_pss = false;
+
+    for (name, value) in fields(&content) {
+        match name {
+            "Rss" => rollup.rss_kb = value,
+            "Pss" => {
+                rollup.pss_kb = value;
+                has_pss = true;
+            }
+            "Pss_Anon" => rollup.pss_anon_kb = value,
+            "Pss_File" => rollup.pss_file_kb = value,
+            "Shared_Clean" => rollup.shared_clean_kb = value,
+            "Shared_Dirty" => rollup.shared_dirty_kb = value,
+            "Private_Clean" => rollup.private_clean_kb = value,
+            "Private_Dirty" => rollup.private_dirty_kb = value,
+            "Swap" => rollup.swap_kb = value,
+            _ => {}
+        }
+    }
+
+    // Exiting process has an empty file
+    has_pss.then_some(rollup)
+}
+
+//
+// Fields of /proc/<pid>/stat after command name,
+// which goes in parentheses and may contain anything
+//
+
+fn stat_fields(pid: u32) -> Option<Vec<String>> {
+    let content = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (_, rest) = content.rsplit_once(')')?;
+    Some(rest.split_whitespace().map(String::from).collect())
+}
+
+// Process state letter, e.g. R, S or T
+pub fn state(pid: u32) -> Option<char> {
+    stat_fields(pid)?.first()?.chars().next()
+}
+
+pub fn parent(pid: u32) -> Option<u32> {
+    stat_fields(pid)?.get(1)?.parse().ok()
+}
@@ -0,0 +1,96 @@
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+//
+// Pseudo-terminal for targets that behave differently
+// when their output is not a TTY. Child writes to the
+// slave side, the runner reads from the master side.
+//
+
+pub struct Pty {
+    pub master: File,
+    // Child gets its own copy for each output stream
+    pub stdout: File,
+    pub stderr: File,
+}
+
+fn last_error(what: &str) -> String {
+    let e = std::io::Error::last_os_error();
+    format!("Failed to {what}. Reason - {e}")
+}
+
+// Plain "\n" endings in logs instead of "\r\n"
+fn disable_crlf(slave: &File) -> Result<(), String> {
+    unsafe {
+        let mut termios: libc::termios = mem::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+            return Err(last_error("get terminal attributes"));
+        }
+
+        termios.c_oflag &= !libc::ONLCR;
+        if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+            return Err(last_error("set terminal attributes"));
+        }
+    }
+
+    Ok(())
+}
+
+// Kernel default is 0x0, which many TUI programs can not handle
+fn set_window_size(slave: &File, rows: u16, cols: u16) -> Result<(), String> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    if unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &size) } != 0 {
+        return Err(last_error("set terminal window size"));
+    }
+
+    Ok(())
+}
+
+pub fn open(rows: u16, cols: u16) -> Result<Pty, String> {
+    let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(last_error("open pseudo-terminal"));
+    }
+
+    let master = unsafe { File::from_raw_fd(fd) };
+
+    if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+        return Err(last_error("unlock pseudo-terminal"));
+    }
+
+    let mut name = [0 as libc::c_char; 128];
+    if unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) } != 0 {
+        return Err(last_error("get pseudo-terminal name"));
+    }
+
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_CLOEXEC)
+        .open(name.to_string_lossy().as_ref());
+
+    let slave = match slave {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to open pseudo-terminal slave. Reason - {e}")),
+    };
+
+    disable_crlf(&slave)?;
+    set_window_size(&slave, rows, cols)?;
+
+    let stderr = match slave.try_clone() {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to duplicate pseudo-terminal slave. Reason - {e}")),
+    };
+
+    Ok(Pty { master, stdout: slave, stderr })
+}
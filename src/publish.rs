@@ -0,0 +1,210 @@
+use crate::config::ResultSink;
+
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+//
+// Sends the result to result_sink once the run is over.
+// Failures are reported to the caller, which only logs
+// them: the run itself is not affected.
+//
+
+pub fn send(sink: &ResultSink, json: &str, timeout: Duration) -> Result<(), String> {
+    let line = format!("{json}\n");
+
+    match sink {
+        ResultSink::File { path } => append_file(path, &line),
+        ResultSink::Unix { path } => send_unix(path, &line, timeout),
+        ResultSink::Tcp { address } => send_tcp(address, &line, timeout),
+        ResultSink::Webhook { url } => post_webhook(url, json, timeout),
+    }
+}
+
+// Appended as a line, so several runs may share one file
+fn append_file(path: &str, line: &str) -> Result<(), String> {
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+
+    match written {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to write result to '{path}'. Reason - {e}")),
+    }
+}
+
+fn send_unix(path: &str, line: &str, timeout: Duration) -> Result<(), String> {
+    let sent = UnixStream::connect(path).and_then(|mut stream| {
+        stream.set_write_timeout(Some(timeout))?;
+        stream.write_all(line.as_bytes())?;
+        stream.shutdown(Shutdown::Both)
+    });
+
+    match sent {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to send result to '{path}'. Reason - {e}")),
+    }
+}
+
+// Every resolved address is tried in turn
+fn connect(address: &str, timeout: Duration) -> Result<TcpStream, String> {
+    let addrs = match address.to_socket_addrs() {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to resolve '{address}'. Reason - {e}")),
+    };
+
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    match last_error {
+        Some(e) => Err(format!("Failed to connect to '{address}'. Reason - {e}")),
+        None => Err(format!("Failed to resolve '{address}'. Reason - no addresses")),
+    }
+}
+
+fn send_tcp(address: &str, line: &str, timeout: Duration) -> Result<(), String> {
+    let mut stream = connect(address, timeout)?;
+
+    let sent = stream
+        .set_write_timeout(Some(timeout))
+        .and_then(|_| stream.write_all(line.as_bytes()))
+        .and_then(|_| stream.shutdown(Shutdown::Both));
+
+    match sent {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to send result to '{address}'. Reason - {e}")),
+    }
+}
+
+//
+// Splits http://host[:port][/path] into address, Host
+// header and path. IPv6 hosts go in brackets, so that
+// their colons are not taken for the port.
+//
+
+pub fn parse_url(url: &str) -> Option<(String, String, &str)> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => rest.split_at(pos),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(val) => match val.split_once(']')? {
+            (host, "") => (host, None),
+            (host, port) => (host, Some(port.strip_prefix(':')?)),
+        },
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+
+    if host.is_empty() {
+        return None;
+    }
+
+    let port = match port {
+        Some(val) => val.parse::<u16>().ok()?,
+        None => 80,
+    };
+
+    let address = match host.contains(':') {
+        true => format!("[{host}]:{port}"),
+        false => format!("{host}:{port}"),
+    };
+
+    Some((address, authority.to_string(), path))
+}
+
+//
+// Minimal HTTP/1.1 POST. Only the status line of
+// the response is read, any 2xx means success.
+//
+
+fn post_webhook(url: &str, json: &str, timeout: Duration) -> Result<(), String> {
+    let (address, host, path) = match parse_url(url) {
+        Some(val) => val,
+        None => return Err(format!("Failed to parse webhook url '{url}'")),
+    };
+
+    let mut stream = connect(&address, timeout)?;
+
+    let request = format!(
+        "POST {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {json}",
+        json.len()
+    );
+
+    let mut status = String::new();
+    let exchanged = stream
+        .set_write_timeout(Some(timeout))
+        .and_then(|_| stream.set_read_timeout(Some(timeout)))
+        .and_then(|_| stream.write_all(request.as_bytes()))
+        .and_then(|_| BufReader::new(&stream).read_line(&mut status));
+
+    if let Err(e) = exchanged {
+        return Err(format!("Failed to post result to '{url}'. Reason - {e}"));
+    }
+
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!("Webhook '{url}' has rejected result. Response - {}", status.trim_end())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(url: &str) -> Option<(String, String, String)> {
+        parse_url(url).map(|(address, host, path)| (address, host, path.to_string()))
+    }
+
+    fn expected(address: &str, host: &str, path: &str) -> Option<(String, String, String)> {
+        Some((address.to_string(), host.to_string(), path.to_string()))
+    }
+
+    #[test]
+    fn port_defaults_to_80() {
+        assert_eq!(parsed("http://ci.local/hook"), expected("ci.local:80", "ci.local", "/hook"));
+        assert_eq!(parsed("http://ci.local:8080/hook"), expected("ci.local:8080", "ci.local:8080", "/hook"));
+    }
+
+    #[test]
+    fn missing_path_is_root() {
+        assert_eq!(parsed("http://10.0.0.1:9000"), expected("10.0.0.1:9000", "10.0.0.1:9000", "/"));
+    }
+
+    #[test]
+    fn ipv6_hosts_are_bracketed() {
+        assert_eq!(parsed("http://[::1]:8080/hook"), expected("[::1]:8080", "[::1]:8080", "/hook"));
+        assert_eq!(parsed("http://[fe80::1]"), expected("[fe80::1]:80", "[fe80::1]", "/"));
+        assert_eq!(parsed("http://::1/hook"), None);
+        assert_eq!(parsed("http://[::1/hook"), None);
+    }
+
+    #[test]
+    fn invalid_urls_are_rejected() {
+        assert_eq!(parsed("https://ci.local/hook"), None);
+        assert_eq!(parsed("ftp://ci.local/hook"), None);
+        assert_eq!(parsed("ci.local/hook"), None);
+        assert_eq!(parsed("http:///hook"), None);
+        assert_eq!(parsed("http://ci.local:/hook"), None);
+        assert_eq!(parsed("http://ci.local:http/hook"), None);
+    }
+}
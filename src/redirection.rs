@@ -1,6 +1,8 @@
-use crate::config::Config;
+use crate::config::{Config, FileMode, Stream, SyslogRedirection};
+use crate::diagnostics;
 
 use std::fs::{File, OpenOptions};
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use subprocess::Redirection;
 
 fn file_read() -> OpenOptions {
@@ -9,7 +11,7 @@ fn file_read() -> OpenOptions {
     opts
 }
 
-fn file_write() -> OpenOptions {
+pub fn file_write() -> OpenOptions {
     let mut opts = File::options();
     opts.truncate(true);
     opts.write(true);
@@ -17,27 +19,155 @@ fn file_write() -> OpenOptions {
     opts
 }
 
-fn stream_redirection(stream: &Option<String>, file: OpenOptions) -> Result<Redirection, String> {
-    let path = stream.clone().unwrap_or("/dev/null".to_string());
-    
-    match file.open(path.clone()) {
+fn file_append() -> OpenOptions {
+    let mut opts = File::options();
+    opts.append(true);
+    opts.create(true);
+    opts
+}
+
+fn open_file(path: &str, file: OpenOptions) -> Result<Redirection, String> {
+    match file.open(path) {
         Ok(fd) => Ok(Redirection::File(fd)),
-        Err(_) => Err(format!("Failed to open file for write. Path: {path}")),
+        Err(_) => Err(format!("Failed to open file. Path: {path}")),
+    }
+}
+
+pub fn dup_fd(fd: i32) -> Result<Redirection, String> {
+    // Popen closes the file it gets, so give it a copy
+    let new_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if new_fd < 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(format!("Failed to duplicate fd {fd}. Reason - {e}"));
+    }
+
+    Ok(Redirection::File(unsafe { File::from_raw_fd(new_fd) }))
+}
+
+fn stream_redirection(stream: &Stream, output: bool) -> Result<Redirection, String> {
+    match stream {
+        Stream::Null if output => open_file("/dev/null", file_write()),
+        Stream::Null => open_file("/dev/null", file_read()),
+        Stream::Inherit => Ok(Redirection::None),
+        Stream::File { path, .. } if !output => open_file(path, file_read()),
+        Stream::File { path, mode: FileMode::Truncate } => open_file(path, file_write()),
+        Stream::File { path, mode: FileMode::Append } => open_file(path, file_append()),
+        Stream::Pipe | Stream::Capture => Ok(Redirection::Pipe),
+        Stream::Fd { fd } => dup_fd(*fd),
+    }
+}
+
+//
+// Destination for output the runner writes on behalf
+// of the child. None means output is dropped.
+// Inherited stream is a copy of the given fd.
+//
+
+pub fn output_file(stream: &Stream, inherited_fd: i32) -> Result<Option<File>, String> {
+    let redirection = match stream {
+        Stream::Null | Stream::Pipe | Stream::Capture => return Ok(None),
+        Stream::Inherit => dup_fd(inherited_fd)?,
+        _ => stream_redirection(stream, true)?,
+    };
+
+    match redirection {
+        Redirection::File(file) => Ok(Some(file)),
+        _ => Ok(None),
+    }
+}
+
+//
+// Hints the kernel that the child reads stdin file
+// from start to end: readahead window is doubled and
+// the whole file is queued for reading into page cache.
+// Advice belongs to the open file the child inherits.
+//
+
+fn advise_sequential(file: &File, path: &str) {
+    let fd = file.as_raw_fd();
+    for advice in [libc::POSIX_FADV_SEQUENTIAL, libc::POSIX_FADV_WILLNEED] {
+        let errno = unsafe { libc::posix_fadvise(fd, 0, 0, advice) };
+        if errno != 0 {
+            let e = std::io::Error::from_raw_os_error(errno);
+            eprintln!("Failed to advise sequential reads. Path: {path}. Reason - {e}");
+            return;
+        }
     }
 }
 
 pub fn stdin_redirection(config: &Config) -> Result<Redirection, String> {
-    Ok(stream_redirection(&config.streams.stdin, file_read())?)
+    let redirection = stream_redirection(&config.streams.stdin, false)?;
+
+    if let (Redirection::File(file), Some(path)) = (&redirection, config.streams.stdin.path()) {
+        if config.streams.sequential_stdin {
+            advise_sequential(file, path);
+        }
+    }
+
+    Ok(redirection)
+}
+
+fn to_syslog(config: &Config, stream: fn(&SyslogRedirection) -> bool) -> bool {
+    config.streams.syslog.as_ref().is_some_and(stream)
+}
+
+// Rotated and reopened files are written by the runner, not by the child
+fn to_runner_file(config: &Config, stream: &Stream) -> bool {
+    let streams = &config.streams;
+    let proxied = streams.rotate_interval_sec.is_some() || streams.reopen_logs_on_sighup;
+    proxied && matches!(stream, Stream::File { .. })
+}
+
+fn same_file(config: &Config) -> bool {
+    let streams = &config.streams;
+    streams.stdout.path().is_some() && streams.stdout.path() == streams.stderr.path()
+}
+
+//
+// Stderr is read by the runner to be matched against
+// fail_on_stderr_pattern. Stdout sharing its file is
+// read as well, so that both write the same file.
+// Both are read to keep their last lines for result.
+//
+
+pub fn to_scanned(config: &Config, stderr: bool) -> bool {
+    let matched = config.fail_on_stderr_pattern.is_some() && (stderr || same_file(config));
+    matched || config.result_tail_lines.is_some()
 }
 
 pub fn stdout_redirection(config: &Config) -> Result<Redirection, String> {
-    Ok(stream_redirection(&config.streams.stdout, file_write())?)
+    // Replaced with pseudo-terminal right before spawn
+    if config.use_pty || config.streams.combined.is_some()
+        || !config.streams.sinks.stdout.is_empty()
+        || to_syslog(config, |s| s.stdout)
+        || to_runner_file(config, &config.streams.stdout)
+        || to_scanned(config, false)
+    {
+        return Ok(Redirection::Pipe);
+    }
+
+    stream_redirection(&config.streams.stdout, true)
 }
 
 pub fn stderr_redirection(config: &Config) -> Result<Redirection, String> {
-    if config.streams.stdout != config.streams.stderr {
-        Ok(stream_redirection(&config.streams.stderr, file_write())?)
-    } else {
+    if config.use_pty || config.streams.combined.is_some()
+        || !config.streams.sinks.stderr.is_empty()
+        || to_syslog(config, |s| s.stderr)
+        || to_runner_file(config, &config.streams.stderr)
+        || to_scanned(config, true)
+    {
+        return Ok(Redirection::Pipe);
+    }
+
+    // Merging into proxied stdout would mix streams in its pipe
+    let stdout_proxied = to_syslog(config, |s| s.stdout);
+
+    if same_file(config) && !stdout_proxied {
         Ok(Redirection::Merge)
+    } else if diagnostics::is_redirected() && config.streams.stderr == Stream::Inherit {
+        // Runner's own stderr now points to runner_log_file
+        dup_fd(diagnostics::child_stderr())
+    } else {
+        stream_redirection(&config.streams.stderr, true)
     }
 }
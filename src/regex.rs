@@ -0,0 +1,89 @@
+use std::ffi::{CStr, CString};
+use std::mem;
+
+//
+// POSIX extended regular expressions from libc,
+// there is no regex crate in the static build.
+// Matching is thread safe once compiled.
+//
+
+pub struct Regex {
+    inner: Box<libc::regex_t>,
+}
+
+unsafe impl Send for Regex {}
+unsafe impl Sync for Regex {}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let c_pattern = match CString::new(pattern) {
+            Ok(val) => val,
+            Err(_) => return Err(String::from("Pattern must not contain NUL bytes")),
+        };
+
+        let mut inner: Box<libc::regex_t> = Box::new(unsafe { mem::zeroed() });
+        let flags = libc::REG_EXTENDED | libc::REG_NOSUB;
+
+        let rc = unsafe { libc::regcomp(inner.as_mut(), c_pattern.as_ptr(), flags) };
+        if rc != 0 {
+            let mut buf = [0u8; 256];
+            unsafe { libc::regerror(rc, inner.as_ref(), buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+            let reason = CStr::from_bytes_until_nul(&buf).map(|val| val.to_string_lossy().into_owned());
+            return Err(format!("Invalid pattern '{pattern}'. Reason - {}", reason.unwrap_or_default()));
+        }
+
+        Ok(Self { inner })
+    }
+
+    // Text is cut at the first NUL byte
+    pub fn is_match(&self, text: &[u8]) -> bool {
+        let end = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+        let c_text = CString::new(&text[..end]).unwrap_or_default();
+
+        unsafe { libc::regexec(self.inner.as_ref(), c_text.as_ptr(), 0, std::ptr::null_mut(), 0) == 0 }
+    }
+}
+
+impl Drop for Regex {
+    fn drop(&mut self) {
+        unsafe { libc::regfree(self.inner.as_mut()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text.as_bytes())
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(is_match("^ERROR", "ERROR: disk full"));
+        assert!(!is_match("^ERROR", "no ERROR here"));
+        assert!(is_match("done$", "all done"));
+        assert!(!is_match("done$", "done twice"));
+    }
+
+    #[test]
+    fn classes_and_repetition() {
+        assert!(is_match("[0-9]+ leaked", "found 42 leaked objects"));
+        assert!(!is_match("[0-9]+ leaked", "found some leaked objects"));
+        assert!(is_match("^[[:alpha:]_]{3,5}$", "ab_c"));
+        assert!(!is_match("^[[:alpha:]_]{3,5}$", "ab"));
+        assert!(is_match("(panic|abort)ed", "thread aborted"));
+    }
+
+    #[test]
+    fn no_match_and_nul_bytes() {
+        assert!(!is_match("AddressSanitizer", "clean exit"));
+        assert!(!Regex::new("x").unwrap().is_match(b"abc\0x"));
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        assert!(Regex::new("(unclosed").is_err());
+        assert!(Regex::new("a\0b").is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use signal_hook::low_level;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+//
+// Logrotate contract: after the file is renamed,
+// SIGHUP makes the runner reopen it by path, so
+// output goes to the new file. Files are reopened
+// lazily by writers, the handler bumps a counter.
+//
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+pub fn register() -> Result<(), String> {
+    if REGISTERED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let handler = || {
+        GENERATION.fetch_add(1, Ordering::SeqCst);
+    };
+
+    // Handler only touches an atomic, which is signal safe
+    match unsafe { low_level::register(libc::SIGHUP, handler) } {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to register SIGHUP handler. Reason - {e}")),
+    }
+}
+
+pub struct ReopenFile {
+    path: String,
+    generation: u64,
+    file: BufWriter<File>,
+    // Reopening never splits a line
+    at_line_start: bool,
+}
+
+impl ReopenFile {
+    pub fn new(path: &str, append: bool) -> Result<Self, String> {
+        let mut options = File::options();
+        options.write(true).create(true);
+
+        if append {
+            options.append(true);
+        } else {
+            options.truncate(true);
+        }
+
+        let file = match options.open(path) {
+            Ok(val) => val,
+            Err(e) => return Err(format!("Failed to open file for write. Path: {path}. Reason - {e}")),
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            generation: GENERATION.load(Ordering::SeqCst),
+            file: BufWriter::new(file),
+            at_line_start: true,
+        })
+    }
+
+    // Renamed file is left as is, a new one is created in its place
+    fn reopen(&mut self) -> io::Result<()> {
+        let generation = GENERATION.load(Ordering::SeqCst);
+        if generation == self.generation {
+            return Ok(());
+        }
+
+        self.file.flush()?;
+        self.file = BufWriter::new(File::options().append(true).create(true).open(&self.path)?);
+        self.generation = generation;
+        Ok(())
+    }
+}
+
+impl Write for ReopenFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.at_line_start {
+            self.reopen()?;
+        }
+
+        let n = self.file.write(buf)?;
+        if n > 0 {
+            self.at_line_start = buf[n - 1] == b'\n';
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+//
+// Sliding window counter of spawns.
+// The first spawn is not a restart, so
+// at most max_restarts + 1 spawns are
+// allowed within the window.
+//
+
+pub struct RestartLimiter {
+    max_restarts: Option<u32>,
+    window: Duration,
+    spawns: VecDeque<Instant>,
+}
+
+impl RestartLimiter {
+    pub fn new(max_restarts: Option<u32>, window: Duration) -> Self {
+        Self {
+            max_restarts,
+            window,
+            spawns: VecDeque::new(),
+        }
+    }
+
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+
+        while let Some(&oldest) = self.spawns.front() {
+            if now.duration_since(oldest) < self.window {
+                break;
+            }
+            self.spawns.pop_front();
+        }
+
+        if let Some(max_restarts) = self.max_restarts {
+            if self.spawns.len() > max_restarts as usize {
+                return false;
+            }
+        }
+
+        self.spawns.push_back(now);
+        true
+    }
+}
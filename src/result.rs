@@ -0,0 +1,270 @@
+use crate::capture::Captured;
+use crate::config::{Config, OutputFormat};
+use crate::procstatus::{ProcStatus, SmapsRollup};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    #[default]
+    Finished,
+    Timeout,
+    Terminated,
+    RestartLimit,
+    ReadyTimeout,
+    Stopped,
+    HealthCheckFailed,
+    StderrMatched,
+    ParentLost,
+    StartTimeout,
+    VerifyFailed,
+    BudgetExhausted,
+    FileLimit,
+    ConfigError,
+    RedirectionError,
+    SpawnError,
+    InternalError,
+}
+
+// Timeout with exit_with_child_code, see code()
+pub const CHILD_TIMEOUT_CODE: i32 = 254;
+
+impl ExitReason {
+    //
+    // Keep in sync with exit code table in README.
+    // Pre-spawn failures use sysexits.h codes.
+    //
+
+    pub fn default_code(&self) -> i32 {
+        match self {
+            ExitReason::Finished => 0,
+            ExitReason::Timeout => 138, // SIGUSR1
+            ExitReason::Terminated => 130, // SIGTERM
+            ExitReason::RestartLimit => 75, // EX_TEMPFAIL
+            ExitReason::ReadyTimeout => 120,
+            ExitReason::Stopped => 121,
+            ExitReason::HealthCheckFailed => 122,
+            ExitReason::ParentLost => 123,
+            ExitReason::StderrMatched => 124,
+            ExitReason::StartTimeout => 125,
+            ExitReason::VerifyFailed => 118,
+            ExitReason::BudgetExhausted => 119,
+            ExitReason::FileLimit => 117,
+            ExitReason::ConfigError => 78, // EX_CONFIG
+            ExitReason::RedirectionError => 73, // EX_CANTCREAT
+            ExitReason::SpawnError => 71, // EX_OSERR
+            ExitReason::InternalError => -1,
+        }
+    }
+
+    //
+    // Transparent wrapper reports termination as a shell
+    // would: 128 + the signal the runner has received.
+    // Timeout gets a code above every 128 + signal one.
+    //
+
+    pub fn code(&self, config: &Config, child_exit_code: Option<u32>, signal: Option<i32>) -> i32 {
+        if let (ExitReason::Finished, true) = (self, config.exit_with_child_code) {
+            if let Some(code) = child_exit_code {
+                return code as i32;
+            }
+        }
+
+        if let Some(&code) = config.exit_codes.get(self) {
+            return code;
+        }
+
+        let configured = match self {
+            ExitReason::Timeout => config.timeout_exit_code,
+            ExitReason::Terminated => config.terminated_exit_code,
+            _ => None,
+        };
+
+        let transparent = match (self, config.exit_with_child_code) {
+            (ExitReason::Timeout, true) => Some(CHILD_TIMEOUT_CODE),
+            (ExitReason::Terminated, true) => Some(128 + signal.unwrap_or(libc::SIGTERM)),
+            _ => None,
+        };
+
+        configured.or(transparent).unwrap_or_else(|| self.default_code())
+    }
+
+    //
+    // Finished run is a success if the child's code is listed
+    // in success_exit_codes, or is 0 when none are listed.
+    // Passed verifier has already decided for the child.
+    //
+
+    pub fn succeeded(&self, config: &Config, child_exit_code: Option<u32>) -> bool {
+        if *self != ExitReason::Finished {
+            return false;
+        }
+
+        if config.verify_command.is_some() {
+            return true;
+        }
+
+        match (child_exit_code, config.success_exit_codes.is_empty()) {
+            (Some(code), false) => config.success_exit_codes.contains(&code),
+            (Some(code), true) => code == 0,
+            (None, _) => false,
+        }
+    }
+}
+
+// What has made the runner stop the child
+#[derive(Serialize, Clone)]
+pub struct ShutdownTrigger {
+    pub reason: ExitReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<String>,
+    pub ts: f64,
+}
+
+//
+// How the wait loop has behaved, for tuning poll interval.
+// Remaining time is negative when the timeout was noticed late.
+//
+
+#[derive(Serialize, Default)]
+pub struct PollStats {
+    pub iterations: u64,
+    pub wait_sec: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_remaining_sec: Option<f64>,
+}
+
+#[derive(Serialize, Default)]
+pub struct RunResult {
+    pub exit_reason: ExitReason,
+    pub runner_exit_code: i32,
+    pub succeeded: bool,
+    pub exit_code: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_signal: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub killed_by_runner: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub child_orphaned: Option<bool>,
+    pub argv: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_alternative: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restarts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub binary_sha256: Option<String>,
+    pub cwd: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_sec: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drain_timed_out: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_truncated_by_timeout: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout_capture: Option<Captured>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr_capture: Option<Captured>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout_tail: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr_tail: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr_match: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_exit_code: Option<u32>,
+    // Of the child alone, as reported when it was reaped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_rss_kb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proc_status_peak: Option<ProcStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smaps_rollup: Option<SmapsRollup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peak_threads: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_limit_reached: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peak_open_files: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_limit_reached: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_trigger: Option<ShutdownTrigger>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_stats: Option<PollStats>,
+}
+
+pub fn print(result: &RunResult, format: OutputFormat) {
+    match format {
+        OutputFormat::Plain => {
+            if let Some(code) = result.exit_code {
+                println!("{}", code);
+            }
+        }
+        OutputFormat::Json => match serde_json::to_string(result) {
+            Ok(val) => println!("{val}"),
+            Err(e) => eprintln!("Failed to serialize result. Reason - {e}"),
+        },
+        OutputFormat::Csv => print_csv(result),
+    }
+}
+
+//
+// Header and a single row of key fields, e.g. to be
+// appended to a spreadsheet. Missing values are empty,
+// none of them may contain a comma or a quote.
+//
+
+fn print_csv(result: &RunResult) {
+    let reason = serde_json::to_value(result.exit_reason).ok();
+    let reason = reason.as_ref().and_then(|val| val.as_str()).unwrap_or_default();
+
+    let fields = [
+        reason.to_string(),
+        result.exit_code.map(|code| code.to_string()).unwrap_or_default(),
+        result.exit_signal.clone().unwrap_or_default(),
+        result.duration_sec.map(|sec| format!("{:.0}", sec * 1000.0)).unwrap_or_default(),
+        result.max_rss_kb.map(|kb| kb.to_string()).unwrap_or_default(),
+    ];
+
+    println!("reason,exit_code,signal,duration_ms,max_rss_kb");
+    println!("{}", fields.join(","));
+}
+
+//
+// Aligned block for humans reading the terminal,
+// framed to stand out from the child's own output
+//
+
+pub fn print_summary(result: &RunResult) {
+    let or_dash = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+
+    let rows = [
+        ("Reason", format!("{:?}", result.exit_reason)),
+        ("Exit code", or_dash(result.exit_code.map(|code| code.to_string()))),
+        ("Signal", or_dash(result.exit_signal.clone())),
+        ("Duration", or_dash(result.duration_sec.map(|sec| format!("{sec:.3} s")))),
+        ("Max RSS", or_dash(result.max_rss_kb.map(|kb| format!("{kb} kB")))),
+        ("Runner exit code", result.runner_exit_code.to_string()),
+    ];
+
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+
+    eprintln!("========== Run summary ==========");
+    for (name, value) in rows {
+        eprintln!("{name:<width$}  {value}");
+    }
+    eprintln!("=================================");
+}
@@ -0,0 +1,81 @@
+use crate::timestamp;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+//
+// Output file split into time buckets aligned to
+// multiples of the interval, e.g. hourly files.
+// Each bucket gets its own file named by bucket
+// start time, old files are kept as they are.
+//
+
+pub struct RotatingFile {
+    path: String,
+    interval: u64,
+    bucket: u64,
+    file: BufWriter<File>,
+    // Rotation never splits a line
+    at_line_start: bool,
+}
+
+fn open(path: &str, bucket: u64) -> io::Result<BufWriter<File>> {
+    let path = format!("{path}.{}", timestamp::utc(bucket).compact());
+
+    // Restarted runner keeps writing into the same bucket
+    let file = File::options().append(true).create(true).open(path)?;
+    Ok(BufWriter::new(file))
+}
+
+impl RotatingFile {
+    pub fn new(path: &str, interval: u64) -> Result<Self, String> {
+        let bucket = timestamp::now() / interval * interval;
+        let file = match open(path, bucket) {
+            Ok(val) => val,
+            Err(e) => return Err(format!("Failed to open file for write. Path: {path}. Reason - {e}")),
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            interval,
+            bucket,
+            file,
+            at_line_start: true,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let bucket = timestamp::now() / self.interval * self.interval;
+        if bucket == self.bucket {
+            return Ok(());
+        }
+
+        self.file.flush()?;
+        self.file = open(&self.path, bucket)?;
+        self.bucket = bucket;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.at_line_start {
+            self.rotate()?;
+        }
+
+        let n = self.file.write(buf)?;
+        if n > 0 {
+            self.at_line_start = buf[n - 1] == b'\n';
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,53 @@
+use std::io;
+use std::mem;
+
+//
+// Resource usage of the child alone: its own and of
+// descendants it has waited for. getrusage() with
+// RUSAGE_CHILDREN would add everything the runner has
+// reaped, hooks and earlier attempts included.
+//
+// waitid() with WNOWAIT reports the usage of an exited
+// child and leaves it to be reaped by subprocess. It is
+// a raw syscall, as libc's wrapper has no rusage argument.
+//
+
+// Largest RSS in kB, None while the child is running
+pub fn max_rss_kb(pid: u32, block: bool) -> io::Result<Option<u64>> {
+    let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+
+    let mut options = libc::WEXITED | libc::WNOWAIT;
+    if !block {
+        options |= libc::WNOHANG;
+    }
+
+    loop {
+        let rc = unsafe {
+            libc::syscall(
+                libc::SYS_waitid,
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info as *mut libc::siginfo_t,
+                options,
+                &mut usage as *mut libc::rusage,
+            )
+        };
+
+        if rc == 0 {
+            break;
+        }
+
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+
+    // WNOHANG leaves siginfo zeroed if nothing has exited
+    if unsafe { info.si_pid() } == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(usage.ru_maxrss as u64))
+}
@@ -0,0 +1,97 @@
+use crate::config::Config;
+use crate::result::{ExitReason, RunResult};
+use crate::run_job;
+use crate::termination::Termination;
+use crate::wakeup::Wakeup;
+
+use serde_json::json;
+
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//
+// Smoke test of a deployment: the runner starts a copy
+// of itself that just sleeps, sends SIGTERM to its own
+// process and checks that the child has been shut down
+// gracefully. Catches blocked or ignored signals before
+// real jobs run into them.
+//
+
+pub const CHILD_ARG: &str = "--sleep";
+
+const SIGNAL_DELAY: Duration = Duration::from_millis(500);
+const GRACE_PERIOD_SEC: u64 = 5;
+
+// Default disposition, so SIGTERM kills it unless blocked
+pub fn sleep_forever() -> ! {
+    loop {
+        thread::sleep(Duration::from_secs(3600));
+    }
+}
+
+fn config() -> Result<Config, String> {
+    let exe = match env::current_exe() {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to locate runner executable. Reason - {e}")),
+    };
+
+    // Global config is not applied, the test must not depend on the host
+    let config = json!({
+        "command": [exe, "selftest", CHILD_ARG],
+        "env": [],
+        "cwd": "/",
+        "streams": {},
+        "poll_interval_ms": 50,
+        "run_timeout_sec": 60,
+        "grace_period_sec": GRACE_PERIOD_SEC,
+    });
+
+    match serde_json::from_value(config) {
+        Ok(val) => Ok(val),
+        Err(e) => Err(format!("Failed to build self-test config. Reason - {e}")),
+    }
+}
+
+fn checks(result: &RunResult, elapsed: Duration) -> [(&'static str, bool); 4] {
+    let trigger_signal = result.shutdown_trigger.as_ref().and_then(|trigger| trigger.signal.as_deref());
+
+    [
+        ("child has started", result.started_at.is_some()),
+        ("SIGTERM has reached the runner", trigger_signal == Some("SIGTERM")),
+        ("run has ended as terminated", result.exit_reason == ExitReason::Terminated),
+        (
+            "child has exited on shutdown signal before grace period",
+            result.exit_signal.as_deref() == Some("SIGTERM") && elapsed < Duration::from_secs(GRACE_PERIOD_SEC),
+        ),
+    ]
+}
+
+pub fn run(term: &Termination, wakeup: &Wakeup) -> bool {
+    let config = match config() {
+        Ok(val) => val,
+        Err(e) => {
+            eprintln!("{e}");
+            println!("FAIL");
+            return false;
+        }
+    };
+
+    thread::spawn(|| {
+        thread::sleep(SIGNAL_DELAY);
+        unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+    });
+
+    let started = Instant::now();
+    let result = run_job(&config, term, wakeup);
+    let elapsed = started.elapsed();
+
+    let mut passed = true;
+    for (name, ok) in checks(&result, elapsed) {
+        println!("{}: {name}", if ok { "PASS" } else { "FAIL" });
+        passed &= ok;
+    }
+
+    println!("{}", if passed { "PASS" } else { "FAIL" });
+    passed
+}
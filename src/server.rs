@@ -0,0 +1,144 @@
+use crate::config;
+use crate::run_job;
+use crate::termination::Termination;
+use crate::wakeup::Wakeup;
+
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//
+// Server mode: each connection sends one config
+// (the client must shut down its write side within
+// REQUEST_TIMEOUT), the runner runs it and replies
+// with result.
+// Jobs are served one at a time. With max_jobs
+// the server exits after that many connections,
+// so a fresh process picks up leaked resources.
+//
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Reads until the client shuts down its write side
+fn read_request(stream: &mut UnixStream, term: &Termination) -> Result<String, String> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut content = Vec::new();
+    let mut buf = [0u8; 4096];
+
+    loop {
+        if term.is_set() {
+            return Err(String::from("Caught SIGTERM while reading request"));
+        }
+
+        if Instant::now() >= deadline {
+            return Err(String::from("Timed out reading request"));
+        }
+
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => content.extend_from_slice(&buf[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    String::from_utf8(content).map_err(|e| e.to_string())
+}
+
+fn handle_connection(mut stream: UnixStream, term: &Termination, wakeup: &Wakeup) {
+    // Short read timeout lets us notice termination signals
+    let setup = stream.set_nonblocking(false).and_then(|_| stream.set_read_timeout(Some(ACCEPT_POLL_INTERVAL)));
+    if let Err(e) = setup {
+        eprintln!("Failed to setup connection. Reason - {e}");
+        return;
+    }
+
+    let content = match read_request(&mut stream, term) {
+        Ok(val) => val,
+        Err(e) => {
+            eprintln!("Failed to read config from connection. Reason - {e}");
+            return;
+        }
+    };
+
+    let response = match config::parse_json(&content) {
+        // Exec would replace the server with the child
+        Ok(config) if config.exec => {
+            eprintln!("Rejected job in exec mode");
+            serde_json::json!({ "error": "exec mode is not supported by server" }).to_string()
+        }
+        Ok(config) => {
+            let run_result = run_job(&config, term, wakeup);
+            match serde_json::to_string(&run_result) {
+                Ok(val) => val,
+                Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to load config. Reason - {e}");
+            serde_json::json!({ "error": e }).to_string()
+        }
+    };
+
+    if let Err(e) = writeln!(stream, "{response}") {
+        eprintln!("Failed to send result. Reason - {e}");
+    }
+}
+
+pub fn serve(path: &str, max_jobs: Option<u64>, term: &Termination, wakeup: &Wakeup) -> Result<(), String> {
+    // Socket left by previous run prevents bind,
+    // but anything else at the path is not ours
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if let Err(e) = fs::remove_file(path) {
+                return Err(format!("Failed to remove stale socket. Reason - {e}"));
+            }
+        }
+        Ok(_) => return Err(format!("Socket path exists and is not a socket. Path: {path}")),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(format!("Failed to check socket path. Reason - {e}")),
+    }
+
+    let listener = match UnixListener::bind(path) {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to bind socket. Reason - {e}")),
+    };
+
+    // Whoever connects gets jobs run as the runner user
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(0o600)) {
+        let _ = fs::remove_file(path);
+        return Err(format!("Failed to restrict socket permissions. Reason - {e}"));
+    }
+
+    // Non-blocking accept lets us notice termination signals
+    if let Err(e) = listener.set_nonblocking(true) {
+        return Err(format!("Failed to setup socket. Reason - {e}"));
+    }
+
+    let mut jobs = 0;
+
+    while !term.is_set() {
+        if max_jobs.is_some_and(|val| jobs >= val) {
+            eprintln!("Served {jobs} jobs. Exitting...");
+            let _ = fs::remove_file(path);
+            return Ok(());
+        }
+
+        match listener.accept() {
+            Ok((stream, _)) => {
+                handle_connection(stream, term, wakeup);
+                jobs += 1;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+            Err(e) => return Err(format!("Failed to accept connection. Reason - {e}")),
+        }
+    }
+
+    eprintln!("Caught SIGTERM. Exitting...");
+    let _ = fs::remove_file(path);
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use serde::Deserialize;
+
+#[rustfmt::skip]
+const SIGNALS: [(&str, i32); 31] = [
+    ("HUP",    libc::SIGHUP),
+    ("INT",    libc::SIGINT),
+    ("QUIT",   libc::SIGQUIT),
+    ("ILL",    libc::SIGILL),
+    ("TRAP",   libc::SIGTRAP),
+    ("ABRT",   libc::SIGABRT),
+    ("BUS",    libc::SIGBUS),
+    ("FPE",    libc::SIGFPE),
+    ("KILL",   libc::SIGKILL),
+    ("USR1",   libc::SIGUSR1),
+    ("SEGV",   libc::SIGSEGV),
+    ("USR2",   libc::SIGUSR2),
+    ("PIPE",   libc::SIGPIPE),
+    ("ALRM",   libc::SIGALRM),
+    ("TERM",   libc::SIGTERM),
+    ("STKFLT", libc::SIGSTKFLT),
+    ("CHLD",   libc::SIGCHLD),
+    ("CONT",   libc::SIGCONT),
+    ("STOP",   libc::SIGSTOP),
+    ("TSTP",   libc::SIGTSTP),
+    ("TTIN",   libc::SIGTTIN),
+    ("TTOU",   libc::SIGTTOU),
+    ("URG",    libc::SIGURG),
+    ("XCPU",   libc::SIGXCPU),
+    ("XFSZ",   libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF",   libc::SIGPROF),
+    ("WINCH",  libc::SIGWINCH),
+    ("IO",     libc::SIGIO),
+    ("PWR",    libc::SIGPWR),
+    ("SYS",    libc::SIGSYS),
+];
+
+const SIGNAL_MAX: i32 = 64; // SIGRTMAX on linux
+
+// Signals the runner shuts down on unless configured otherwise
+pub const SHUTDOWN_SIGNALS: [i32; 2] = [libc::SIGINT, libc::SIGTERM];
+
+//
+// Accepts "15", "TERM", "SIGTERM", "sigterm", e.t.c
+//
+
+pub fn parse(value: &str) -> Result<i32, String> {
+    let value = value.trim();
+
+    if let Ok(num) = value.parse::<i32>() {
+        return from_number(num);
+    }
+
+    let upper = value.to_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+
+    match SIGNALS.iter().find(|(n, _)| *n == name) {
+        Some((_, num)) => Ok(*num),
+        None => Err(format!("Unknown signal: '{value}'")),
+    }
+}
+
+fn from_number(num: i32) -> Result<i32, String> {
+    if (1..=SIGNAL_MAX).contains(&num) {
+        Ok(num)
+    } else {
+        Err(format!("Signal number out of range: {num}"))
+    }
+}
+
+pub fn name(num: i32) -> String {
+    match SIGNALS.iter().find(|(_, n)| *n == num) {
+        Some((name, _)) => format!("SIG{name}"),
+        None => format!("signal {num}"),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SignalSpec {
+    Number(i32),
+    Name(String),
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(try_from = "SignalSpec")]
+pub struct Signal(pub i32);
+
+impl TryFrom<SignalSpec> for Signal {
+    type Error = String;
+
+    fn try_from(spec: SignalSpec) -> Result<Self, Self::Error> {
+        match spec {
+            SignalSpec::Number(num) => from_number(num).map(Signal),
+            SignalSpec::Name(name) => parse(&name).map(Signal),
+        }
+    }
+}
+
+impl Default for Signal {
+    fn default() -> Self {
+        Signal(libc::SIGTERM)
+    }
+}
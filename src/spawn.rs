@@ -0,0 +1,123 @@
+use crate::preexec::{Report, StepError};
+use crate::procstatus;
+
+use subprocess::{Popen, PopenConfig};
+
+use std::ffi::OsString;
+use std::fs;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+//
+// Spawn may block forever on a hung filesystem, e.g.
+// exec of a binary on a dead network mount. With a
+// timeout it runs in a thread of its own, which kills
+// the child if it shows up after the runner gave up.
+//
+
+pub type Spawned = (Popen, Option<StepError>);
+
+// Rc based redirections are never used by the runner
+struct SendConfig(PopenConfig);
+
+unsafe impl Send for SendConfig {}
+
+fn create(argv: &[OsString], pconf: PopenConfig, report: Option<Report>) -> Result<Spawned, String> {
+    let ps = match Popen::create(argv, pconf) {
+        Ok(val) => val,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    // Exec of the target by pre-exec copy may hang as well
+    Ok((ps, report.and_then(|report| report.read())))
+}
+
+//
+// Pid is not known until create returns. Runner has
+// no other children while spawning, so all of them
+// are killed. Child blocked in the kernel dies once
+// the filesystem call returns.
+//
+
+fn kill_children() {
+    let runner_pid = std::process::id();
+    let entries = match fs::read_dir("/proc") {
+        Ok(val) => val,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let pid = entry.file_name().to_str().and_then(|val| val.parse::<u32>().ok());
+        if let Some(pid) = pid.filter(|&pid| procstatus::parent(pid) == Some(runner_pid)) {
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        }
+    }
+}
+
+//
+// Returns None on timeout. Report, if any,
+// is read within the same time budget.
+//
+
+pub fn spawn(
+    argv: Vec<OsString>,
+    pconf: PopenConfig,
+    report: Option<Report>,
+    timeout: Option<Duration>,
+) -> Result<Option<Spawned>, String> {
+    let timeout = match timeout {
+        Some(val) => val,
+        None => return create(&argv, pconf, report).map(Some),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let abandoned = Arc::new(Mutex::new(false));
+
+    let thread_abandoned = Arc::clone(&abandoned);
+    let pconf = SendConfig(pconf);
+
+    let spawned = thread::Builder::new().name(String::from("spawn")).spawn(move || {
+        // Moves the wrapper, not just its non-Send field
+        let pconf = pconf;
+        let result = create(&argv, pconf.0, report);
+
+        // Flag and channel are checked under the same lock
+        let abandoned = thread_abandoned.lock().unwrap_or_else(|e| e.into_inner());
+        match result {
+            Ok((mut ps, _)) if *abandoned => {
+                let _ = ps.kill();
+                let _ = ps.wait();
+            }
+            result => {
+                let _ = tx.send(result);
+            }
+        }
+    });
+
+    if let Err(e) = spawned {
+        return Err(format!("Failed to start spawn thread. Reason - {e}"));
+    }
+
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result.map(Some),
+        Err(RecvTimeoutError::Timeout) => {
+            let mut abandoned = abandoned.lock().unwrap_or_else(|e| e.into_inner());
+            *abandoned = true;
+
+            // Child may have been sent right before the flag was set
+            match rx.try_recv() {
+                Ok(Ok((mut ps, _))) => {
+                    let _ = ps.kill();
+                    let _ = ps.wait();
+                }
+                Ok(Err(_)) => {}
+                Err(_) => kill_children(),
+            }
+
+            Ok(None)
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(String::from("Spawn thread has exited unexpectedly")),
+    }
+}
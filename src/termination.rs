@@ -0,0 +1,56 @@
+use crate::timestamp;
+
+use signal_hook::low_level;
+
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+//
+// Remembers the first termination signal and
+// the wall clock time it has arrived at, so a
+// signal racing with a timeout can be ordered.
+//
+
+#[derive(Default)]
+pub struct Termination {
+    signal: AtomicI32,
+    ts_us: AtomicU64,
+}
+
+impl Termination {
+    pub fn register(signals: &[i32]) -> Result<Arc<Self>, String> {
+        let term = Arc::new(Self::default());
+
+        for &signal in signals {
+            let handler_term = Arc::clone(&term);
+            let handler = move || handler_term.record(signal);
+
+            // Handler only touches atomics and clock_gettime, both are signal safe
+            if let Err(e) = unsafe { low_level::register(signal, handler) } {
+                return Err(format!("Failed to register signal handler. Reason - {e}"));
+            }
+        }
+
+        Ok(term)
+    }
+
+    fn record(&self, signal: i32) {
+        let ts_us = (timestamp::now_precise() * 1e6) as u64;
+
+        // Time goes first, readers look at the signal
+        let _ = self.ts_us.compare_exchange(0, ts_us, Ordering::SeqCst, Ordering::SeqCst);
+        let _ = self.signal.compare_exchange(0, signal, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.signal.load(Ordering::SeqCst) != 0
+    }
+
+    // Signal number and unix time it has arrived at
+    pub fn signal(&self) -> Option<(i32, f64)> {
+        match self.signal.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some((signal, self.ts_us.load(Ordering::SeqCst) as f64 / 1e6)),
+        }
+    }
+}
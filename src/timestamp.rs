@@ -0,0 +1,119 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//
+// Minimal UTC calendar math, enough
+// for naming files and logging.
+//
+
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+pub fn now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(val) => val.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+// Unix time with sub-second precision
+pub fn now_precise() -> f64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(val) => val.as_secs_f64(),
+        Err(_) => 0.0,
+    }
+}
+
+// Days to civil date, see Howard Hinnant's "chrono-Compatible Low-Level Date Algorithms"
+pub fn utc(secs: u64) -> DateTime {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    DateTime {
+        year,
+        month,
+        day,
+        hour: (rem / 3600) as u32,
+        minute: (rem % 3600 / 60) as u32,
+        second: (rem % 60) as u32,
+    }
+}
+
+// RFC 3339 in UTC with milliseconds, e.g. 2024-01-31T23:59:59.123Z
+pub fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let dt = utc(since_epoch.as_secs());
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        dt.year, dt.month, dt.day, dt.hour, dt.minute, dt.second,
+        since_epoch.subsec_millis()
+    )
+}
+
+impl DateTime {
+    // Safe for file names, e.g. 20240131T235959Z
+    pub fn compact(&self) -> String {
+        format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn compact(secs: u64) -> String {
+        utc(secs).compact()
+    }
+
+    #[test]
+    fn epoch_is_first_day_of_1970() {
+        assert_eq!(compact(0), "19700101T000000Z");
+    }
+
+    #[test]
+    fn year_boundary() {
+        assert_eq!(compact(1704067199), "20231231T235959Z");
+        assert_eq!(compact(1704067200), "20240101T000000Z");
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(compact(1709164799), "20240228T235959Z");
+        assert_eq!(compact(1709164800), "20240229T000000Z");
+        // Divisible by 400, so 2000 is a leap year
+        assert_eq!(compact(951782400), "20000229T000000Z");
+        // Divisible by 100 only, so 2100 is not
+        assert_eq!(compact(4107456000), "21000228T000000Z");
+        assert_eq!(compact(4107542400), "21000301T000000Z");
+    }
+
+    #[test]
+    fn milliseconds_are_zero_padded() {
+        let time = |millis| UNIX_EPOCH + Duration::from_secs(1704067199) + Duration::from_millis(millis);
+        assert_eq!(rfc3339(time(7)), "2023-12-31T23:59:59.007Z");
+        assert_eq!(rfc3339(time(45)), "2023-12-31T23:59:59.045Z");
+        assert_eq!(rfc3339(time(0)), "2023-12-31T23:59:59.000Z");
+        assert_eq!(rfc3339(time(999)), "2023-12-31T23:59:59.999Z");
+    }
+}
@@ -0,0 +1,133 @@
+use crate::config::{Config, SinkSpec, Stream};
+use crate::result::ExitReason;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+//
+// Output paths may contain {reason} placeholder to sort
+// artifacts by exit reason. The reason is known only at
+// exit, so files are written with "pending" in place of
+// it and moved once the run is over, e.g.
+// out/{reason}/stdout.log -> out/pending/stdout.log
+// -> out/timeout/stdout.log
+//
+
+pub const PLACEHOLDER: &str = "{reason}";
+const PENDING: &str = "pending";
+
+pub struct ReasonPath {
+    pub pending: String,
+    pub template: String,
+}
+
+fn output_paths(config: &mut Config) -> Vec<&mut String> {
+    let streams = &mut config.streams;
+    let mut paths = Vec::new();
+
+    for stream in [&mut streams.stdout, &mut streams.stderr] {
+        if let Stream::File { path, .. } = stream {
+            paths.push(path);
+        }
+    }
+
+    if let Some(path) = &mut streams.combined {
+        paths.push(path);
+    }
+
+    let sinks = streams.sinks.stdout.iter_mut().chain(streams.sinks.stderr.iter_mut());
+    for sink in sinks {
+        if let SinkSpec::File { path, .. } = sink {
+            paths.push(path);
+        }
+    }
+
+    paths
+}
+
+//
+// Rewrites output paths to pending ones, so the
+// rest of the runner deals with plain paths only
+//
+
+pub fn resolve(config: &mut Config) {
+    let mut reason_paths: Vec<ReasonPath> = Vec::new();
+
+    for path in output_paths(config) {
+        if !path.contains(PLACEHOLDER) {
+            continue;
+        }
+
+        let template = path.clone();
+        *path = template.replace(PLACEHOLDER, PENDING);
+
+        // Streams sharing a file are moved once
+        if !reason_paths.iter().any(|val| val.template == template) {
+            reason_paths.push(ReasonPath { pending: path.clone(), template });
+        }
+    }
+
+    config.reason_paths = reason_paths;
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+// Pending directories are made by the runner, files are opened right after
+pub fn prepare(config: &Config) -> Result<(), String> {
+    for path in &config.reason_paths {
+        if let Err(e) = create_parent(Path::new(&path.pending)) {
+            return Err(format!("Failed to create directory for '{}'. Reason - {e}", path.pending));
+        }
+    }
+
+    Ok(())
+}
+
+//
+// Rename is atomic but works only within one filesystem.
+// Across filesystems the file is copied and then removed,
+// so a reader may see it partially written meanwhile.
+//
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    create_parent(to)?;
+
+    match fs::rename(from, to) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+pub fn sort(config: &Config, reason: ExitReason) {
+    let name = serde_json::to_value(reason).ok();
+    let name = name.as_ref().and_then(|val| val.as_str()).unwrap_or(PENDING);
+
+    for path in &config.reason_paths {
+        let pending = Path::new(&path.pending);
+
+        // Nothing to move if the file has never been opened
+        if !pending.exists() {
+            continue;
+        }
+
+        let target = path.template.replace(PLACEHOLDER, name);
+        match move_file(pending, Path::new(&target)) {
+            Ok(()) => eprintln!("Moved '{}' to '{target}'", path.pending),
+            Err(e) => eprintln!("Failed to move '{}' to '{target}'. Reason - {e}", path.pending),
+        }
+
+        // Fails while other files are left there
+        if let Some(dir) = pending.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
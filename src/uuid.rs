@@ -0,0 +1,34 @@
+use std::fs::File;
+use std::io::Read;
+
+//
+// Random (version 4) UUID generated from /dev/urandom
+// Avoids pulling extra crates into the static binary
+//
+
+pub fn v4() -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+
+    let mut urandom = match File::open("/dev/urandom") {
+        Ok(val) => val,
+        Err(e) => return Err(format!("Failed to open /dev/urandom. Reason - {e}")),
+    };
+
+    if let Err(e) = urandom.read_exact(&mut bytes) {
+        return Err(format!("Failed to read /dev/urandom. Reason - {e}"));
+    }
+
+    bytes[6] = (bytes[6] & 0x0f) | 0x40; // version 4
+    bytes[8] = (bytes[8] & 0x3f) | 0x80; // variant RFC 4122
+
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
@@ -0,0 +1,68 @@
+use signal_hook::low_level::pipe;
+
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+//
+// Self-pipe written by signal handlers.
+// Lets the wait loop sleep until either
+// the child exits (SIGCHLD) or the runner
+// is asked to stop, instead of waking up
+// only once per poll interval.
+//
+
+pub struct Wakeup {
+    read: UnixStream,
+}
+
+impl Wakeup {
+    pub fn new(signals: &[i32]) -> Result<Self, String> {
+        let (read, write) = match UnixStream::pair() {
+            Ok(val) => val,
+            Err(e) => return Err(format!("Failed to create wakeup pipe. Reason - {e}")),
+        };
+
+        if let Err(e) = read.set_nonblocking(true) {
+            return Err(format!("Failed to setup wakeup pipe. Reason - {e}"));
+        }
+
+        for &signal in signals {
+            let write = match write.try_clone() {
+                Ok(val) => val,
+                Err(e) => return Err(format!("Failed to setup wakeup pipe. Reason - {e}")),
+            };
+
+            if let Err(e) = pipe::register(signal, write) {
+                return Err(format!("Failed to register wakeup handler. Reason - {e}"));
+            }
+        }
+
+        Ok(Self { read })
+    }
+
+    // Blocks until a signal arrives or timeout expires
+    pub fn wait(&self, timeout: Duration) {
+        let mut fds = [libc::pollfd {
+            fd: self.read.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+
+        // Rounded up, sub-millisecond waits would spin otherwise
+        let timeout_ms = timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+        unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout_ms) };
+
+        self.drain();
+    }
+
+    fn drain(&self) {
+        let mut buf = [0u8; 64];
+        while let Ok(n) = (&self.read).read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+}
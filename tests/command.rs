@@ -0,0 +1,61 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn tool_in_dir(dir: &str, name: &str) -> PathBuf {
+    let dir = env::temp_dir().join("runner-tests").join(dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let tool = dir.join(name);
+    fs::write(&tool, "#!/bin/sh\nexit 0\n").unwrap();
+    fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+    tool
+}
+
+#[test]
+fn alternative_is_found_in_prepended_path() {
+    let tool = tool_in_dir("alternative-bin", "runner-alternative-tool");
+    let dir = tool.parent().unwrap();
+
+    // Pre-exec steps exec the child from the runner re-spawned with them
+    for pre_exec in [false, true] {
+        let mut config = common::base_config(&["sh", "-c", "exit 7"]);
+        config["command_alternatives"] = serde_json::json!([["runner-alternative-tool"]]);
+        config["env"] = serde_json::json!([{ "name": "PATH", "value": dir, "mode": "prepend" }]);
+        config["new_session"] = pre_exec.into();
+        config["output_format"] = "json".into();
+
+        let run = common::run("command_alternative_path", &config);
+        let result = common::result(&run);
+
+        assert_eq!(run.output.status.code(), Some(0));
+        assert_eq!(result["command_alternative"], 0);
+        assert_eq!(result["exit_code"], 0);
+        assert_eq!(result["argv"][0], tool.to_str().unwrap());
+    }
+}
+
+#[test]
+fn hashed_binary_is_found_in_prepended_path() {
+    let tool = tool_in_dir("hashed-bin", "runner-hashed-tool");
+    let dir = tool.parent().unwrap();
+
+    let mut config = common::base_config(&["runner-hashed-tool"]);
+    config["env"] = serde_json::json!([{ "name": "PATH", "value": dir, "mode": "prepend" }]);
+    config["hash_binary"] = true.into();
+    config["output_format"] = "json".into();
+
+    let run = common::run("command_hashed_path", &config);
+    let result = common::result(&run);
+
+    let output = Command::new("sha256sum").arg(&tool).output().unwrap();
+    let digest = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(run.output.status.code(), Some(0));
+    assert_eq!(result["binary_sha256"], digest.split_whitespace().next().unwrap());
+    assert_eq!(result["argv"][0], tool.to_str().unwrap());
+}
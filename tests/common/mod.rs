@@ -0,0 +1,59 @@
+// Each test binary uses only some of the helpers
+#![allow(dead_code)]
+
+use serde_json::Value;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::time::{Duration, Instant};
+
+pub struct Run {
+    pub output: Output,
+    pub elapsed: Duration,
+}
+
+pub fn config_path(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join("runner-tests");
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(format!("{name}.json"))
+}
+
+pub fn base_config(command: &[&str]) -> Value {
+    serde_json::json!({
+        "command": command,
+        "env": [],
+        "cwd": env::temp_dir(),
+        "streams": {
+            "stdin": null,
+            "stdout": null,
+            "stderr": null
+        },
+        "poll_interval_ms": 100,
+        "run_timeout_sec": 600,
+        "grace_period_sec": 5
+    })
+}
+
+pub fn run(name: &str, config: &Value) -> Run {
+    let path = config_path(name);
+    fs::write(&path, config.to_string()).unwrap();
+
+    let start = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_runner"))
+        .arg(&path)
+        .output()
+        .unwrap();
+
+    Run {
+        output,
+        elapsed: start.elapsed(),
+    }
+}
+
+// JSON result printed with output_format json
+pub fn result(run: &Run) -> Value {
+    let stdout = String::from_utf8_lossy(&run.output.stdout);
+    serde_json::from_str(stdout.trim()).unwrap()
+}
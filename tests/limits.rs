@@ -0,0 +1,43 @@
+mod common;
+
+use std::time::Duration;
+
+#[test]
+fn failure_at_open_files_limit_is_reported() {
+    //
+    // Shell holds ten descriptors and saves its stdin
+    // to the eleventh while sleep runs, as redirections
+    // of a command are undone after it.
+    //
+
+    let script = "exec 3</dev/null 4</dev/null 5</dev/null 6</dev/null 7</dev/null 8</dev/null 9</dev/null; sleep 0.5 <&3; exit 24";
+    let mut config = common::base_config(&["sh", "-c", script]);
+    config["limits"] = serde_json::json!({ "max_open_files": 11 });
+    config["poll_interval_ms"] = 50.into();
+    config["output_format"] = "json".into();
+
+    let run = common::run("limits_open_files", &config);
+    let result = common::result(&run);
+
+    assert_eq!(run.output.status.code(), Some(117));
+    assert_eq!(result["exit_reason"], "file_limit");
+    assert_eq!(result["file_limit_reached"], true);
+    assert_eq!(result["peak_open_files"], 11);
+    assert_eq!(result["exit_code"], 24);
+    assert!(run.elapsed >= Duration::from_millis(500));
+}
+
+#[test]
+fn success_below_open_files_limit_is_finished() {
+    let mut config = common::base_config(&["sh", "-c", "exec 3</dev/null; sleep 0.3"]);
+    config["limits"] = serde_json::json!({ "max_open_files": 64 });
+    config["poll_interval_ms"] = 50.into();
+    config["output_format"] = "json".into();
+
+    let run = common::run("limits_open_files_below", &config);
+    let result = common::result(&run);
+
+    assert_eq!(run.output.status.code(), Some(0));
+    assert_eq!(result["exit_reason"], "finished");
+    assert_eq!(result["file_limit_reached"], false);
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+#[test]
+fn megabytes_through_pipes_do_not_block_child() {
+    let script = "head -c 32000000 /dev/zero; head -c 8000000 /dev/zero >&2";
+    let mut config = common::base_config(&["sh", "-c", script]);
+    config["streams"]["stdout"] = serde_json::json!({ "type": "pipe" });
+    config["streams"]["stderr"] = serde_json::json!({ "type": "pipe" });
+    config["run_timeout_sec"] = 60.into();
+    config["output_format"] = "json".into();
+
+    let run = common::run("output_megabytes_pipes", &config);
+    let result = common::result(&run);
+
+    assert_eq!(run.output.status.code(), Some(0));
+    assert_eq!(result["exit_reason"], "finished");
+    assert_eq!(result["stdout_bytes"], 32000000);
+    assert_eq!(result["stderr_bytes"], 8000000);
+    assert!(run.elapsed < Duration::from_secs(30));
+}
+
+#[test]
+fn megabytes_through_combined_file_keep_every_line() {
+    let path = env::temp_dir().join("runner-tests").join("output_megabytes_combined.log");
+    let _ = fs::remove_file(&path);
+
+    let mut config = common::base_config(&["seq", "1", "500000"]);
+    config["streams"] = serde_json::json!({ "combined": path });
+    config["run_timeout_sec"] = 60.into();
+
+    let run = common::run("output_megabytes_combined", &config);
+    assert_eq!(run.output.status.code(), Some(0));
+
+    let content = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+
+    assert_eq!(lines.len(), 500000);
+    assert_eq!(lines[0], "[out] 1");
+    assert_eq!(lines[499999], "[out] 500000");
+}
+
+#[test]
+fn timeout_fires_while_child_floods_pipe() {
+    let mut config = common::base_config(&["yes"]);
+    config["streams"]["stdout"] = serde_json::json!({ "type": "pipe" });
+    config["run_timeout_sec"] = 1.into();
+    config["grace_period_sec"] = 1.into();
+
+    let run = common::run("output_flood_timeout", &config);
+
+    assert_eq!(run.output.status.code(), Some(138));
+    assert!(run.elapsed < Duration::from_secs(5));
+}
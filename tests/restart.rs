@@ -0,0 +1,44 @@
+mod common;
+
+use std::time::Duration;
+
+#[test]
+fn crash_loop_stops_at_restart_limit() {
+    let mut config = common::base_config(&["false"]);
+    config["restart_policy"] = "on-failure".into();
+    config["max_restarts"] = 2.into();
+    config["restart_window_sec"] = 60.into();
+    config["output_format"] = "json".into();
+
+    let run = common::run("restart_crash_loop", &config);
+    let result = common::result(&run);
+
+    assert_eq!(run.output.status.code(), Some(75));
+    assert_eq!(result["exit_reason"], "restart_limit");
+    assert_eq!(result["restarts"], 2);
+    assert_eq!(result["exit_code"], 1);
+    assert!(run.elapsed < Duration::from_secs(5));
+}
+
+#[test]
+fn restarts_outside_window_are_not_limited() {
+    let script = "n=$(cat restart_window.cnt 2>/dev/null || echo 0); echo $((n + 1)) > restart_window.cnt; [ $n -ge 3 ]";
+    let dir = std::env::temp_dir().join("runner-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+    let _ = std::fs::remove_file(dir.join("restart_window.cnt"));
+
+    let mut config = common::base_config(&["sh", "-c", script]);
+    config["cwd"] = dir.to_string_lossy().into_owned().into();
+    config["restart_policy"] = "on-failure".into();
+    config["max_restarts"] = 1.into();
+    config["restart_window_sec"] = 1.into();
+    config["start_delay_sec"] = 1.into();
+    config["output_format"] = "json".into();
+
+    let run = common::run("restart_window", &config);
+    let result = common::result(&run);
+
+    assert_eq!(run.output.status.code(), Some(0));
+    assert_eq!(result["exit_reason"], "finished");
+    assert_eq!(result["restarts"], 3);
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use std::time::Duration;
+
+// Keeps 30 MB buffered, far more than the child needs
+const MEMORY_HOG: &str = "head -c 40000000 /dev/zero | tail -c 30000000 >/dev/null";
+
+#[test]
+fn max_rss_is_of_the_child_alone() {
+    let mut config = common::base_config(&["true"]);
+    config["verify_command"] = serde_json::json!(["sh", "-c", MEMORY_HOG]);
+    config["output_format"] = "json".into();
+
+    let run = common::run("result_max_rss", &config);
+    let result = common::result(&run);
+
+    assert_eq!(result["exit_reason"], "finished");
+    let max_rss_kb = result["max_rss_kb"].as_u64().unwrap();
+    assert!(max_rss_kb > 0 && max_rss_kb < 20_000, "{max_rss_kb}");
+    assert!(run.elapsed < Duration::from_secs(10));
+}
+
+#[test]
+fn csv_max_rss_is_of_the_child_alone() {
+    let mut config = common::base_config(&["true"]);
+    config["verify_command"] = serde_json::json!(["sh", "-c", MEMORY_HOG]);
+    config["output_format"] = "csv".into();
+
+    let run = common::run("result_csv_max_rss", &config);
+    let stdout = String::from_utf8_lossy(&run.output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+
+    assert_eq!(lines[0], "reason,exit_code,signal,duration_ms,max_rss_kb");
+    let fields: Vec<&str> = lines[1].split(',').collect();
+    assert_eq!(&fields[..3], ["finished", "0", ""]);
+    let max_rss_kb: u64 = fields[4].parse().unwrap();
+    assert!(max_rss_kb > 0 && max_rss_kb < 20_000, "{max_rss_kb}");
+}
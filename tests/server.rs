@@ -0,0 +1,44 @@
+mod common;
+
+use serde_json::Value;
+
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn exec_job_is_rejected() {
+    let socket = common::config_path("server_exec").with_extension("sock");
+    let _ = std::fs::remove_file(&socket);
+
+    let mut server = Command::new(env!("CARGO_BIN_EXE_runner"))
+        .args(["--serve", socket.to_str().unwrap(), "--max-jobs", "1"])
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let started = Instant::now();
+    while !socket.exists() {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut config = common::base_config(&["sleep", "3"]);
+    config["exec"] = true.into();
+
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    stream.write_all(config.to_string().as_bytes()).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let response: Value = serde_json::from_str(response.trim()).unwrap();
+
+    assert_eq!(response["error"], "exec mode is not supported by server");
+    assert_eq!(server.wait().unwrap().code(), Some(0));
+    assert!(started.elapsed() < Duration::from_secs(3));
+    assert!(!socket.exists());
+}
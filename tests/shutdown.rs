@@ -0,0 +1,106 @@
+mod common;
+
+use std::fs;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Shell runs the trap only after the current sleep returns
+const HANDLES_SIGTERM: &str = "trap 'exit 3' TERM; while :; do sleep 0.1; done";
+const IGNORES_SIGTERM: &str = "trap '' TERM; while :; do sleep 0.1; done";
+
+#[test]
+fn fast_exit_does_not_wait_whole_grace_period() {
+    let mut config = common::base_config(&["sh", "-c", HANDLES_SIGTERM]);
+    config["run_timeout_sec"] = 1.into();
+    config["grace_period_sec"] = 30.into();
+    config["output_format"] = "json".into();
+
+    let run = common::run("shutdown_fast_exit", &config);
+    let result = common::result(&run);
+
+    assert_eq!(run.output.status.code(), Some(138));
+    assert_eq!(result["exit_code"], 3);
+    assert!(run.elapsed < Duration::from_secs(5));
+}
+
+#[test]
+fn settle_time_delays_fast_exit() {
+    let mut config = common::base_config(&["sh", "-c", HANDLES_SIGTERM]);
+    config["run_timeout_sec"] = 1.into();
+    config["grace_period_sec"] = 30.into();
+    config["shutdown_settle_ms"] = 2000.into();
+    config["output_format"] = "json".into();
+
+    let run = common::run("shutdown_settle", &config);
+    let result = common::result(&run);
+
+    assert_eq!(result["exit_code"], 3);
+    assert!(run.elapsed >= Duration::from_secs(3));
+    assert!(run.elapsed < Duration::from_secs(10));
+}
+
+#[test]
+fn ignored_sigterm_is_followed_by_sigkill_after_grace_period() {
+    let mut config = common::base_config(&["sh", "-c", IGNORES_SIGTERM]);
+    config["run_timeout_sec"] = 1.into();
+    config["grace_period_sec"] = 2.into();
+    config["shutdown_poll_ms"] = 10.into();
+    config["output_format"] = "json".into();
+
+    let run = common::run("shutdown_ignore_sigterm", &config);
+    let result = common::result(&run);
+
+    assert_eq!(run.output.status.code(), Some(138));
+    assert_eq!(result["exit_code"], 128 + 9);
+    assert!(run.elapsed >= Duration::from_secs(3));
+    assert!(run.elapsed < Duration::from_secs(6));
+}
+
+#[test]
+fn stop_file_with_old_mtime_triggers_when_seen() {
+    let script = "sleep 0.3; touch -d 2000-01-01 shutdown_stop.flag; while :; do sleep 0.1; done";
+    let mut config = common::base_config(&["sh", "-c", script]);
+    config["stop_file"] = "shutdown_stop.flag".into();
+    config["output_format"] = "json".into();
+
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let run = common::run("shutdown_stop_file", &config);
+    let result = common::result(&run);
+
+    assert_eq!(run.output.status.code(), Some(121));
+    assert_eq!(result["exit_reason"], "stopped");
+    assert!(result["shutdown_trigger"]["ts"].as_f64().unwrap() >= started.as_secs_f64());
+    assert!(run.elapsed < Duration::from_secs(5));
+}
+
+#[test]
+fn child_code_wrapper_reports_received_signal() {
+    let mut config = common::base_config(&["sh", "-c", "exit 3"]);
+    config["exit_with_child_code"] = true.into();
+    assert_eq!(common::run("shutdown_child_code_finished", &config).output.status.code(), Some(3));
+
+    for (signal, code) in [("INT", 130), ("TERM", 143), ("HUP", 129)] {
+        let mut config = common::base_config(&["sleep", "1000"]);
+        config["exit_with_child_code"] = true.into();
+        config["handle_signals"] = serde_json::json!(["INT", "TERM", "HUP"]);
+
+        let path = common::config_path(&format!("shutdown_child_code_{signal}"));
+        fs::write(&path, config.to_string()).unwrap();
+
+        let started = SystemTime::now();
+        let mut runner = Command::new(env!("CARGO_BIN_EXE_runner"))
+            .arg(&path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(500));
+
+        let pid = runner.id().to_string();
+        assert!(Command::new("kill").args(["-s", signal, &pid]).status().unwrap().success());
+
+        assert_eq!(runner.wait().unwrap().code(), Some(code));
+        assert!(started.elapsed().unwrap() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use std::time::Duration;
+
+#[test]
+fn timeout_fires_with_uneven_poll_interval() {
+    let mut config = common::base_config(&["sleep", "1000"]);
+    config["run_timeout_sec"] = 1.into();
+    config["poll_interval_ms"] = 300.into();
+    config["grace_period_sec"] = 1.into();
+
+    let run = common::run("timeout_uneven_poll", &config);
+
+    assert_eq!(run.output.status.code(), Some(138));
+    assert!(run.elapsed >= Duration::from_secs(1));
+    assert!(run.elapsed < Duration::from_secs(5));
+}
+
+#[test]
+fn child_code_wrapper_times_out_above_signal_codes() {
+    let mut config = common::base_config(&["sleep", "1000"]);
+    config["run_timeout_sec"] = 1.into();
+    config["grace_period_sec"] = 1.into();
+    config["exit_with_child_code"] = true.into();
+
+    let run = common::run("timeout_child_code", &config);
+
+    assert_eq!(run.output.status.code(), Some(254));
+    assert!(run.elapsed < Duration::from_secs(5));
+}